@@ -1,9 +1,18 @@
-// n^p
-// power(x, 2)
-// power: u -> (p: nat) -> u^p
+/// power: u -> (p: nat) -> u^p
 int power(int n, int p) {
     int result = 1;
     while (p--)
         result *= n;
     return result;
 }
+
+int main() {
+    /// x: m
+    int x;
+
+    /// area: m^2
+    int area = power(x, 2);
+
+    int n = 3;
+    int volume = power(x, n);
+}
@@ -0,0 +1,21 @@
+#include <algorithm>
+#include <cmath>
+
+int main() {
+    /// x: m
+    double x;
+
+    /// t: s
+    double t;
+
+    /// area: m^2
+    double area = x * x;
+
+    /// side: cm
+    double side = sqrt(area);
+
+    /// longest: m
+    double longest = std::max(x, side);
+
+    double growth = exp(t);
+}
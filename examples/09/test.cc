@@ -0,0 +1,26 @@
+namespace geodesy {
+    double distance(double lat1, double lon1, double lat2, double lon2);
+}
+
+namespace ros {
+    class Duration {
+    public:
+        double toSec() const;
+    };
+}
+
+int main() {
+    /// lat: deg
+    double lat;
+
+    /// lon: rad
+    double lon;
+
+    /// d: cm
+    double d = geodesy::distance(lat, lon, lat, lon);
+
+    ros::Duration elapsed;
+
+    /// t: s
+    double t = elapsed.toSec();
+}
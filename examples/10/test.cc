@@ -0,0 +1,25 @@
+struct Vec3 {
+    double x, y, z;
+};
+
+Vec3 operator+(const Vec3 &a, const Vec3 &b) {
+    return Vec3{a.x + b.x, a.y + b.y, a.z + b.z};
+}
+
+Vec3 operator*(double k, const Vec3 &v) {
+    return Vec3{k * v.x, k * v.y, k * v.z};
+}
+
+int main() {
+    /// position: m
+    Vec3 position;
+
+    /// offset: cm
+    Vec3 offset;
+
+    /// target: m
+    Vec3 target = position + offset;
+
+    /// scaled: m
+    Vec3 scaled = 10.0 * offset;
+}
@@ -0,0 +1,33 @@
+int main() {
+    /// raw: m
+    int raw;
+
+    /// period: s
+    int period;
+
+    /// wrapped: m
+    int wrapped = raw % period;
+
+    /// scaled: m
+    int scaled = raw << 4;
+
+    int flags = raw & 0xff;
+
+    /// height: m
+    double height;
+
+    /// speed: m/s
+    double speed;
+
+    // Comparisons are truth values, so they can be combined and negated.
+    bool airborne = height > 0.5 && speed > 1.0;
+    bool grounded = !(height > 0.5);
+
+    // Only the comparison of a length with a time is reported.
+    bool confused = height > period;
+
+    // Shifting in place rescales the value, like `shifted = shifted << 2`.
+    /// shifted: m
+    int shifted = raw;
+    shifted <<= 2;
+}
@@ -0,0 +1,25 @@
+struct Pose {
+    /// lat: deg
+    double lat;
+};
+
+int main() {
+    /// x: m
+    double x;
+
+    /// y_cm: cm
+    double y_cm;
+
+    double &r = x;
+    r = y_cm;
+
+    double *p = &x;
+    *p = y_cm;
+
+    /// lat_rad: rad
+    double lat_rad;
+
+    Pose pose;
+    Pose *pp = &pose;
+    pp->lat = lat_rad;
+}
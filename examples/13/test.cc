@@ -0,0 +1,22 @@
+#include <vector>
+
+int main() {
+    /// ranges: m
+    double ranges[360];
+
+    /// timestamps: s
+    std::vector<double> timestamps;
+
+    /// closest: cm
+    double closest = ranges[0];
+
+    /// t: s
+    double t = timestamps.at(0);
+
+    timestamps.push_back(closest);
+
+    for (double r : ranges) {
+        /// total: m
+        double total = r;
+    }
+}
@@ -0,0 +1,47 @@
+/// Meters: m
+using Meters = double;
+
+/// Centimeters: cm
+using Centimeters = double;
+
+template <typename Unit>
+class Filter {
+public:
+    void update(double measurement);
+    void reset();
+
+    Unit estimate;
+};
+
+template <typename Unit>
+void Filter<Unit>::update(double measurement) {
+    /// reading: m
+    double reading = measurement;
+    estimate = reading;
+}
+
+// Never called, so it is not walked in either instantiation.
+template <typename Unit>
+void Filter<Unit>::reset() {
+    /// zero: s
+    double zero = 0.0;
+    estimate = zero;
+}
+
+template <typename Unit>
+Unit twice(Unit value) {
+    return value + value;
+}
+
+int main() {
+    Filter<Meters> meters;
+    Filter<Centimeters> centimeters;
+
+    meters.update(1.0);
+    /// total: cm
+    double total = meters.estimate;
+
+    /// offset: cm
+    Centimeters offset = 2.0;
+    centimeters.estimate = twice(offset);
+}
@@ -0,0 +1,39 @@
+#include <functional>
+
+double scale(double distance) {
+    return 10.0 * distance;
+}
+
+/// now: s
+double now();
+
+int main() {
+    /// total: m
+    double total;
+
+    /// step: cm
+    double step;
+
+    auto accumulate = [&](double delta) {
+        total = total + delta;
+        return total;
+    };
+    accumulate(step);
+
+    std::function<double(double)> callback = accumulate;
+
+    /// result: cm
+    double result = callback(step);
+
+    double (*transform)(double) = &scale;
+
+    /// scaled: m
+    double scaled = transform(step);
+
+    // Direct calls have the unit of the function's result.
+    /// elapsed: cm
+    double elapsed = now();
+
+    /// direct: m
+    double direct = scale(step);
+}
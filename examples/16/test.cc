@@ -0,0 +1,18 @@
+class Pose {
+public:
+    Pose(double lat_deg);
+
+    /// lat_: rad
+    double lat_;
+
+    /// timeout_: s
+    double timeout_ = 5.0;
+};
+
+Pose::Pose(double lat_deg) : lat_(lat_deg * 1e-7) {}
+
+void wait(double dt = 0.01);
+
+int main() {
+    Pose pose(10.0);
+}
@@ -0,0 +1,4 @@
+#include "odometer.h"
+
+/// total_distance: m
+double total_distance = 0.0;
@@ -0,0 +1 @@
+extern double total_distance;
@@ -0,0 +1,7 @@
+#include "odometer.h"
+
+int main() {
+    /// trip: cm
+    double trip = 250.0;
+    total_distance = trip;
+}
@@ -0,0 +1,11 @@
+/// altitude: m
+extern double altitude;
+
+/// altitude: cm
+double altitude = 0.0;
+
+int main() {
+    /// reading: m
+    double reading = 120.0;
+    altitude = reading;
+}
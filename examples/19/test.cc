@@ -0,0 +1,34 @@
+/// Meters: m
+using Meters = double;
+
+/// Centimeters: cm
+using Centimeters = double;
+
+class Sensor {
+public:
+    virtual ~Sensor() = default;
+
+    /// distance: m
+    virtual double distance() const = 0;
+
+    virtual void calibrate(Meters offset) = 0;
+};
+
+class Sonar : public Sensor {
+public:
+    /// distance: cm
+    double distance() const override {
+        return 150.0;
+    }
+
+    void calibrate(Centimeters offset) override {
+        offset_ = offset;
+    }
+
+    Centimeters offset_ = 0.0;
+};
+
+int main() {
+    Sonar sonar;
+    sonar.calibrate(2.0);
+}
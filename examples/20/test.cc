@@ -0,0 +1,18 @@
+#define DEG2RAD(x) ((x) * 0.017453292519943295) /// DEG2RAD: rad
+
+/// MAX_RANGE: m
+#define MAX_RANGE 120.0
+
+#define MOUNT_OFFSET 35.0 /// MOUNT_OFFSET: cm
+
+int main() {
+    /// heading: deg
+    double heading = 90.0;
+
+    /// bearing: rad
+    double bearing = DEG2RAD(heading);
+
+    /// range: m
+    double range = MAX_RANGE;
+    range = MOUNT_OFFSET;
+}
@@ -0,0 +1,15 @@
+int main() {
+#ifdef USE_GPS_TIME
+    /// stamp: s
+    /// frame(stamp) = (_, epoch)
+    double stamp = 0.0;
+#else
+    /// stamp: s
+    /// frame(stamp) = (_, boot)
+    double stamp = 0.0;
+#endif
+
+    /// deadline: s
+    /// frame(deadline) = (_, boot)
+    double deadline = stamp;
+}
@@ -0,0 +1,25 @@
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+struct reading {
+    /// range: m
+    double range;
+
+    /// stamp: s
+    double stamp;
+};
+
+union sample {
+    /// meters: m
+    double meters;
+
+    /// centimeters: cm
+    double centimeters;
+};
+
+struct reading make_reading(void);
+
+#ifdef __cplusplus
+}
+#endif
@@ -0,0 +1,8 @@
+#include "sensor.h"
+
+int main() {
+    struct reading r = make_reading();
+
+    /// range: m
+    double range = r.range;
+}
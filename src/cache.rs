@@ -0,0 +1,136 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{commands, walker};
+
+// What parsing and walking a translation unit produced.
+#[derive(Serialize, Deserialize)]
+pub struct Analysis {
+    // Clang's warnings and errors, which are printed again when the entry is used.
+    pub clang_diagnostics: Vec<String>,
+    pub errors: usize,
+
+    // The walk, unless the translation unit was skipped for its errors.
+    pub walk: Option<walker::SavedWalk>,
+}
+
+// An analysis with the files it was parsed from and the hashes of their contents.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    dependencies: Vec<(PathBuf, String)>,
+    analysis: Analysis,
+}
+
+// An on-disk cache of the walk of each translation unit, so that unchanged translation units are
+// neither parsed nor walked again, only solved. Entries are keyed by the command that compiles the
+// translation unit and the settings of the run, and are only used while the source file and every
+// header it included still have the contents they were analyzed with.
+pub struct Cache {
+    directory: PathBuf,
+    settings: String,
+}
+
+// Hashes with SHA-256, which unlike the standard library's hasher is the same in every build.
+fn hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn hash_contents(path: &Path) -> Option<String> {
+    std::fs::read(path).ok().map(|contents| hash(&contents))
+}
+
+impl Cache {
+    // Opens the cache in a directory, creating it if needed. The settings are everything besides
+    // the compile command that affects the analysis, such as the signature database.
+    pub fn new(directory: &Path, settings: &[String]) -> Result<Cache, Box<dyn Error>> {
+        std::fs::create_dir_all(directory)?;
+        let settings = serde_json::to_string(&(env!("CARGO_PKG_VERSION"), settings))?;
+        Ok(Cache {
+            directory: directory.to_path_buf(),
+            settings: hash(settings.as_bytes()),
+        })
+    }
+
+    // The key of a compile command, given the arguments it is finally parsed with.
+    pub fn key(&self, cmd: &commands::CompileCommand, arguments: &[String]) -> String {
+        let command = serde_json::json!([
+            self.settings,
+            cmd.directory,
+            cmd.filename,
+            cmd.contents,
+            arguments
+        ]);
+        hash(command.to_string().as_bytes())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("{}.json", key))
+    }
+
+    // Returns the cached analysis if none of the translation unit's files have changed.
+    pub fn lookup(&self, key: &str) -> Option<Analysis> {
+        let json = std::fs::read_to_string(self.path(key)).ok()?;
+        let entry: Entry = serde_json::from_str(&json).ok()?;
+        entry
+            .dependencies
+            .iter()
+            .all(|(path, hash)| hash_contents(path).as_ref() == Some(hash))
+            .then_some(entry.analysis)
+    }
+
+    // Stores the analysis of a translation unit, which depends on its source file unless it was
+    // parsed from unsaved contents, and on every header it included.
+    pub fn store(
+        &self,
+        key: &str,
+        tu: &clang::TranslationUnit,
+        cmd: &commands::CompileCommand,
+        analysis: Analysis,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut dependencies = get_included_files(tu, &cmd.filename);
+        if cmd.contents.is_none() {
+            dependencies.push(cmd.filename.clone());
+        }
+        let dependencies = dependencies
+            .into_iter()
+            .map(|path| {
+                hash_contents(&path)
+                    .map(|hash| (path.clone(), hash))
+                    .ok_or_else(|| format!("could not read {}", path.display()))
+            })
+            .collect::<Result<Vec<(PathBuf, String)>, String>>()?;
+        let json = serde_json::to_string(&Entry {
+            dependencies,
+            analysis,
+        })?;
+
+        // Written under a temporary name first, so that workers never read a partial entry.
+        let path = self.path(key);
+        let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::write(&temporary, json)?;
+        std::fs::rename(&temporary, &path)?;
+        Ok(())
+    }
+}
+
+// Returns every file a source file included, directly or through other headers.
+fn get_included_files(tu: &clang::TranslationUnit, filename: &Path) -> Vec<PathBuf> {
+    let mut included = Vec::new();
+    let mut visited = HashSet::new();
+    let mut pending: Vec<clang::source::File> = tu.get_file(filename).into_iter().collect();
+    while let Some(file) = pending.pop() {
+        for include in file.get_includes() {
+            if let Some(header) = include.get_file() {
+                if visited.insert(header.get_path()) {
+                    included.push(header.get_path());
+                    pending.push(header);
+                }
+            }
+        }
+    }
+    included
+}
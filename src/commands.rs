@@ -0,0 +1,363 @@
+use std::error::Error;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::util;
+
+// A compile command with its paths rewritten for this machine.
+pub struct CompileCommand {
+    pub directory: PathBuf,
+    pub filename: PathBuf,
+    pub arguments: Vec<String>,
+
+    // Source text to parse instead of reading the file, e.g. a snippet from stdin.
+    pub contents: Option<String>,
+}
+
+// Rewrites path prefixes, e.g. from the machine a compilation database was generated on.
+pub struct PathMap {
+    rules: Vec<(String, String)>,
+}
+
+impl PathMap {
+    // Parses rules of the form `OLD=NEW`.
+    pub fn from_rules(rules: &[String]) -> Result<PathMap, Box<dyn Error>> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                rule.split_once('=')
+                    .map(|(old, new)| (String::from(old), String::from(new)))
+                    .ok_or_else(|| format!("malformed path map {}, expected OLD=NEW", rule))
+            })
+            .collect::<Result<Vec<(String, String)>, String>>()?;
+        Ok(PathMap { rules })
+    }
+
+    // Applies the first rule whose old prefix starts the path in the text, which may follow a flag
+    // such as `-I` or `-isystem`. The prefix must end at a path component, so `/src` does not
+    // match `/srv` or `/src2`.
+    fn apply(&self, text: &str) -> String {
+        for (old, new) in &self.rules {
+            let matched = text.match_indices(old.as_str()).find(|(index, _)| {
+                let flag = &text[..*index];
+                let rest = &text[index + old.len()..];
+                (flag.is_empty() || (flag.starts_with('-') && !flag.contains('/')))
+                    && (rest.is_empty() || rest.starts_with('/') || old.ends_with('/'))
+            });
+            if let Some((index, _)) = matched {
+                // Joined with one separator, whether or not OLD and NEW end with one.
+                let rest = &text[index + old.len()..];
+                let rest = match new.ends_with('/') {
+                    true => rest.strip_prefix('/').unwrap_or(rest),
+                    false if !rest.is_empty() && !rest.starts_with('/') => &format!("/{}", rest),
+                    false => rest,
+                };
+                return format!("{}{}{}", &text[..index], new, rest);
+            }
+        }
+        String::from(text)
+    }
+
+    fn apply_to_path(&self, path: &Path) -> PathBuf {
+        PathBuf::from(self.apply(&path.to_string_lossy()))
+    }
+}
+
+// Guesses how a database that was moved from elsewhere maps onto its new location. The deepest
+// directory the old path and the database's directory end with in common is assumed to be the
+// same, e.g. `/Users/me/scalpel/examples/09` and `/build/scalpel/examples/09` share `examples/09`.
+// Without a common ending, the old directory is assumed to be the database's directory itself.
+fn reroot(old_directory: &Path, database_directory: &Path) -> (String, String) {
+    let old: Vec<_> = old_directory.components().collect();
+    let new: Vec<_> = database_directory.components().collect();
+    let common = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take_while(|(o, n)| o == n)
+        .count();
+
+    let old_root: PathBuf = old[..old.len() - common].iter().collect();
+    let new_root: PathBuf = new[..new.len() - common].iter().collect();
+    (
+        old_root.to_string_lossy().to_string(),
+        new_root.to_string_lossy().to_string(),
+    )
+}
+
+// Reads the compile commands of a database, remapping the paths in its directories, files and
+// arguments. Commands whose directory does not exist even after remapping are re-rooted at the
+// database's own location.
+pub fn from_database(
+    db: &clang::CompilationDatabase,
+    database_directory: &Path,
+    mut path_map: PathMap,
+) -> Vec<CompileCommand> {
+    let commands = db.get_all_compile_commands();
+    let mut result = Vec::new();
+    for cmd in commands.get_commands() {
+        let mut directory = path_map.apply_to_path(&cmd.get_directory());
+        if !directory.exists() {
+            let (old, new) = reroot(&directory, database_directory);
+            if !old.is_empty() && old != new {
+                eprintln!(
+                    "Warning: {} does not exist, mapping {} to {}.",
+                    directory.display(),
+                    old,
+                    new
+                );
+                path_map.rules.push((old, new));
+                directory = path_map.apply_to_path(&directory);
+            }
+        }
+
+        result.push(CompileCommand {
+            directory,
+            filename: path_map.apply_to_path(&cmd.get_filename()),
+            arguments: cmd
+                .get_arguments()
+                .iter()
+                .map(|argument| path_map.apply(argument))
+                .collect(),
+            contents: None,
+        });
+    }
+    result
+}
+
+// Builds the compile command for a single file, or for a snippet read from stdin if the file is
+// `-`. A language overrides the one detected from the file. Snippets are compiled as C++ unless a
+// language is given, and C++ snippets as C++17 unless a standard is given.
+pub fn single_file(
+    file: &str,
+    language: Option<&str>,
+    arguments: &[String],
+) -> Result<CompileCommand, Box<dyn Error>> {
+    let directory = std::env::current_dir()?;
+    let mut arguments = arguments.to_vec();
+    if let Some(language) = language {
+        arguments.splice(0..0, [String::from("-x"), String::from(language)]);
+    }
+
+    let (filename, contents) = if file == "-" {
+        let mut contents = String::new();
+        std::io::stdin().read_to_string(&mut contents)?;
+        let name = match util::detect_language(&arguments, Path::new("stdin.cc")) {
+            util::Language::C => "stdin.c",
+            util::Language::Cxx => "stdin.cc",
+        };
+        (directory.join(name), Some(contents))
+    } else {
+        (directory.join(file), None)
+    };
+
+    let language = util::detect_language(&arguments, &filename);
+    if contents.is_some()
+        && language == util::Language::Cxx
+        && !arguments
+            .iter()
+            .any(|argument| argument.starts_with("-std="))
+    {
+        arguments.push(String::from("-std=c++17"));
+    }
+    arguments.insert(0, String::from(language.default_compiler()));
+
+    Ok(CompileCommand {
+        directory,
+        filename,
+        arguments,
+        contents,
+    })
+}
+
+// Selects the translation units and headers to analyze.
+pub struct FileFilter {
+    include: Vec<regex::Regex>,
+    exclude: Vec<regex::Regex>,
+    header_filter: Option<regex::Regex>,
+    system_headers: bool,
+}
+
+// Translates a glob such as `src/**/*.cc` into a regex that matches paths ending with it.
+fn glob_to_regex(glob: &str) -> Result<regex::Regex, regex::Error> {
+    let mut pattern = String::from("(^|/)");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    pattern += "(.*/)?";
+                } else {
+                    pattern += ".*";
+                }
+            }
+            '*' => pattern += "[^/]*",
+            '?' => pattern += "[^/]",
+            _ => pattern += &regex::escape(&c.to_string()),
+        }
+    }
+    pattern += "$";
+    regex::Regex::new(&pattern)
+}
+
+impl FileFilter {
+    pub fn new(
+        include: &[String],
+        exclude: &[String],
+        header_filter: Option<&str>,
+        system_headers: bool,
+    ) -> Result<FileFilter, Box<dyn Error>> {
+        let compile = |globs: &[String]| {
+            globs
+                .iter()
+                .map(|glob| glob_to_regex(glob))
+                .collect::<Result<Vec<regex::Regex>, regex::Error>>()
+        };
+        Ok(FileFilter {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+            header_filter: header_filter.map(regex::Regex::new).transpose()?,
+            system_headers,
+        })
+    }
+
+    // Whether to analyze the translation unit of a source file.
+    pub fn includes_source(&self, path: &Path) -> bool {
+        let path = path.to_string_lossy();
+        (self.include.is_empty() || self.include.iter().any(|glob| glob.is_match(&path)))
+            && !self.exclude.iter().any(|glob| glob.is_match(&path))
+    }
+
+    // Whether to analyze the code in a header. Declarations in skipped headers are still typed by
+    // their annotations.
+    pub fn includes_header(&self, path: &Path, is_system_header: bool) -> bool {
+        let path = path.to_string_lossy();
+        (self.system_headers || !is_system_header)
+            && self
+                .header_filter
+                .as_ref()
+                .is_none_or(|filter| filter.is_match(&path))
+            && !self.exclude.iter().any(|glob| glob.is_match(&path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path_map(rules: &[&str]) -> PathMap {
+        PathMap::from_rules(
+            &rules
+                .iter()
+                .map(|rule| rule.to_string())
+                .collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn path_map_matches_whole_components() {
+        let map = path_map(&["/src=/home/me/src"]);
+        assert_eq!(map.apply("/src/a.cc"), "/home/me/src/a.cc");
+        assert_eq!(map.apply("/src"), "/home/me/src");
+        assert_eq!(map.apply("/srv/a.cc"), "/srv/a.cc");
+        assert_eq!(map.apply("/src2/a.cc"), "/src2/a.cc");
+    }
+
+    #[test]
+    fn path_map_matches_only_at_start_of_path() {
+        let map = path_map(&["/src=/new"]);
+        assert_eq!(map.apply("/build/src/a.cc"), "/build/src/a.cc");
+        assert_eq!(map.apply("-I/src/include"), "-I/new/include");
+        assert_eq!(map.apply("-isystem/src/include"), "-isystem/new/include");
+        assert_eq!(map.apply("--sysroot=/src"), "--sysroot=/new");
+        assert_eq!(map.apply("-DPATH=/a/src/b"), "-DPATH=/a/src/b");
+    }
+
+    #[test]
+    fn path_map_joins_trailing_separators() {
+        assert_eq!(path_map(&["/old/=/new"]).apply("/old/a.cc"), "/new/a.cc");
+        assert_eq!(path_map(&["/old=/new/"]).apply("/old/a.cc"), "/new/a.cc");
+        assert_eq!(path_map(&["/old/=/new/"]).apply("/old/a.cc"), "/new/a.cc");
+        assert_eq!(
+            path_map(&["/old/=/new/"]).apply("/older/a.cc"),
+            "/older/a.cc"
+        );
+    }
+
+    #[test]
+    fn path_map_applies_first_matching_rule() {
+        let map = path_map(&["/a/b=/x", "/a=/y"]);
+        assert_eq!(map.apply("/a/b/c"), "/x/c");
+        assert_eq!(map.apply("/a/c"), "/y/c");
+    }
+
+    #[test]
+    fn path_map_rejects_rules_without_separator() {
+        assert!(PathMap::from_rules(&[String::from("/old")]).is_err());
+    }
+
+    #[test]
+    fn reroot_keeps_common_ending() {
+        let (old, new) = reroot(
+            Path::new("/Users/me/scalpel/examples/09"),
+            Path::new("/build/scalpel/examples/09"),
+        );
+        assert_eq!((old.as_str(), new.as_str()), ("/Users/me", "/build"));
+    }
+
+    #[test]
+    fn reroot_without_common_ending_maps_whole_directory() {
+        let (old, new) = reroot(Path::new("/Users/me/project"), Path::new("/build/checkout"));
+        assert_eq!(
+            (old.as_str(), new.as_str()),
+            ("/Users/me/project", "/build/checkout")
+        );
+    }
+
+    #[test]
+    fn reroot_ignores_trailing_separators() {
+        let (old, new) = reroot(Path::new("/a/x/y/"), Path::new("/b/x/y"));
+        assert_eq!((old.as_str(), new.as_str()), ("/a", "/b"));
+    }
+
+    #[test]
+    fn reroot_result_maps_paths_under_database() {
+        let (old, new) = reroot(Path::new("/x/examples/09"), Path::new("/examples/09"));
+        let map = path_map(&[&format!("{}={}", old, new)]);
+        assert_eq!(map.apply("/x/examples/09/test.cc"), "/examples/09/test.cc");
+    }
+
+    #[test]
+    fn glob_star_stays_within_component() {
+        let glob = glob_to_regex("src/*.cc").unwrap();
+        assert!(glob.is_match("/p/src/a.cc"));
+        assert!(glob.is_match("src/a.cc"));
+        assert!(!glob.is_match("/p/src/b/a.cc"));
+        assert!(!glob.is_match("/p/mysrc/a.cc"));
+    }
+
+    #[test]
+    fn glob_double_star_crosses_components() {
+        let glob = glob_to_regex("src/**/*.cc").unwrap();
+        assert!(glob.is_match("/p/src/a.cc"));
+        assert!(glob.is_match("/p/src/b/c/a.cc"));
+        assert!(!glob.is_match("/p/src2/a.cc"));
+        assert!(!glob.is_match("/p/src/a.h"));
+
+        let glob = glob_to_regex("third_party/**").unwrap();
+        assert!(glob.is_match("/p/third_party/lib/x.h"));
+        assert!(!glob.is_match("/p/third_party"));
+    }
+
+    #[test]
+    fn glob_escapes_regex_characters() {
+        let glob = glob_to_regex("a+b/?.c").unwrap();
+        assert!(glob.is_match("/p/a+b/x.c"));
+        assert!(!glob.is_match("/p/aab/x.c"));
+        assert!(!glob.is_match("/p/a+b/xy.c"));
+        assert!(!glob.is_match("/p/a+b/x_c"));
+    }
+}
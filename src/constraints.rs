@@ -1,9 +1,10 @@
 use crate::types::{self, SIBaseUnits, NUM_BASE_UNITS};
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, rc::Rc};
 
 pub const COLUMNS_PER_OBJECT: usize = 1 + NUM_BASE_UNITS;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum Constraint {
     // Both of the constraints must be true.
     And(Rc<Constraint>, Rc<Constraint>),
@@ -87,12 +88,9 @@ pub fn assert_equal(obj1: Rc<Object>, obj2: Rc<Object>) -> Rc<Constraint> {
 }
 
 // Asserts that the numeric literal has the scalar multiple represented by it.
-pub fn assert_literal(x: f64, literal_label: &str) -> Rc<Constraint> {
+pub fn assert_literal(x: f64, literal: Rc<Object>) -> Rc<Constraint> {
     Rc::new(Constraint::Equation(Rc::new(Equation {
-        term: Rc::new(Term::Object(
-            Rc::new(Object::new(literal_label)),
-            Selector::ScalarPrefix,
-        )),
+        term: Rc::new(Term::Object(literal, Selector::ScalarPrefix)),
         value: (1. / x).log10(),
     })))
 }
@@ -241,7 +239,76 @@ pub fn create_division_type(
     })
 }
 
-#[derive(Clone, Debug)]
+// Creates a new type by raising base to a constant exponent.
+pub fn create_power_type(
+    result_type: Rc<Object>,
+    base: Rc<Object>,
+    exponent: f64,
+) -> Rc<Constraint> {
+    let mut eqs: Vec<Rc<Equation>> = vec![Rc::new(Equation {
+        term: Rc::new(Term::Sub(
+            Rc::new(Term::Object(result_type.clone(), Selector::ScalarPrefix)),
+            Rc::new(Term::Scale(
+                exponent,
+                Rc::new(Term::Object(base.clone(), Selector::ScalarPrefix)),
+            )),
+        )),
+        value: 0.0,
+    })];
+    for dimension in 0..types::NUM_BASE_UNITS {
+        eqs.push(Rc::new(Equation {
+            term: Rc::new(Term::Sub(
+                Rc::new(Term::Object(
+                    result_type.clone(),
+                    Selector::BaseUnit(types::SIBaseUnits::from(dimension)),
+                )),
+                Rc::new(Term::Scale(
+                    exponent,
+                    Rc::new(Term::Object(
+                        base.clone(),
+                        Selector::BaseUnit(types::SIBaseUnits::from(dimension)),
+                    )),
+                )),
+            )),
+            value: 0.0,
+        }));
+    }
+
+    conjoin(eqs)
+}
+
+// Asserts that the object has no base units. Its scalar prefix is left alone.
+pub fn assert_dimensionless(obj: Rc<Object>) -> Rc<Constraint> {
+    let eqs: Vec<Rc<Equation>> = (0..types::NUM_BASE_UNITS)
+        .map(|dimension| {
+            Rc::new(Equation {
+                term: Rc::new(Term::Object(
+                    obj.clone(),
+                    Selector::BaseUnit(types::SIBaseUnits::from(dimension)),
+                )),
+                value: 0.0,
+            })
+        })
+        .collect();
+
+    conjoin(eqs)
+}
+
+// Joins at least two equations into a single constraint.
+fn conjoin(mut eqs: Vec<Rc<Equation>>) -> Rc<Constraint> {
+    let and1 = Rc::new(Constraint::And(
+        Rc::new(Constraint::Equation(eqs[0].clone())),
+        Rc::new(Constraint::Equation(eqs[1].clone())),
+    ));
+    eqs.split_off(2).into_iter().fold(and1, |constraint, eq| {
+        Rc::new(Constraint::And(
+            constraint,
+            Rc::new(Constraint::Equation(eq)),
+        ))
+    })
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Equation {
     term: Rc<Term>,
     value: f64,
@@ -253,7 +320,7 @@ impl std::fmt::Display for Equation {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 enum Selector {
     BaseUnit(types::SIBaseUnits),
     ScalarPrefix,
@@ -265,10 +332,11 @@ impl std::fmt::Display for Selector {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 enum Term {
     Add(Rc<Term>, Rc<Term>),
     Sub(Rc<Term>, Rc<Term>),
+    Scale(f64, Rc<Term>),
     Object(Rc<Object>, Selector),
 }
 
@@ -277,17 +345,35 @@ impl std::fmt::Display for Term {
         match self {
             Term::Add(l, r) => write!(f, "Add({}, {})", l, r),
             Term::Sub(l, r) => write!(f, "Sub({}, {})", l, r),
+            Term::Scale(c, t) => write!(f, "Scale({}, {})", c, t),
             Term::Object(obj, s) => write!(f, "{}.{}", obj, s),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Object {
+    // Uniquely identifies the object, e.g. by the clang USR of its declaration.
+    pub id: String,
+
     // A human-readable description of the object.
     pub label: String,
 }
 
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Object {}
+
+impl std::hash::Hash for Object {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
 impl std::fmt::Display for Object {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.label)
@@ -295,8 +381,9 @@ impl std::fmt::Display for Object {
 }
 
 impl Object {
-    pub fn new(label: &str) -> Object {
+    pub fn with_label(id: &str, label: &str) -> Object {
         Object {
+            id: String::from(id),
             label: String::from(label),
         }
     }
@@ -312,13 +399,16 @@ fn add_term_to_map(term: &Rc<Term>, object_to_column: &mut HashMap<Object, i32>)
             add_term_to_map(t1, object_to_column);
             add_term_to_map(t2, object_to_column);
         }
+        Term::Scale(_, t) => {
+            add_term_to_map(t, object_to_column);
+        }
         Term::Object(o, _) => {
             if let None = object_to_column.get(o) {
                 let max_id = object_to_column
                     .values()
                     .reduce(|max, col| if col > max { col } else { max });
 
-                object_to_column.insert(Object::new(&o.label), *max_id.unwrap_or(&-1) + 1);
+                object_to_column.insert(o.as_ref().clone(), *max_id.unwrap_or(&-1) + 1);
             }
         }
     }
@@ -364,6 +454,14 @@ fn add_term_to_row(
                 row[i] -= tmp[i];
             }
         }
+        Term::Scale(c, t) => {
+            let mut tmp = vec![0.0; row.len()];
+            add_term_to_row(t, object_to_column_offset, &mut tmp);
+
+            for i in 0..tmp.len() {
+                row[i] += c * tmp[i];
+            }
+        }
         Term::Object(object, selector) => {
             let idx = (*object_to_column_offset.get(object).unwrap() as usize) * COLUMNS_PER_OBJECT;
             match selector {
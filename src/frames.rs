@@ -134,6 +134,16 @@ pub fn parse_human_frame<'a>(str: &'a str) -> Option<(&'a str, InertialFrames, T
     return None;
 }
 
+// Parses a bare frame such as `(global, _)`.
+pub fn parse_frame_tuple(str: &str) -> Option<(InertialFrames, TemporalFrames)> {
+    let frame_regex =
+        regex::Regex::new("^\\s*\\((local|global|_), (boot|epoch|_)\\)\\s*$").unwrap();
+    frame_regex.captures(str).map(|caps| {
+        let (_, [iframe, tframe]) = caps.extract();
+        (iframe.into(), tframe.into())
+    })
+}
+
 fn frame_number(frame: (&InertialFrames, &TemporalFrames)) -> i64 {
     let iframe: i64 = frame.0.into();
     let tframe: i64 = frame.1.into();
@@ -165,6 +175,10 @@ pub fn get_frame_var_name(object_name: &str) -> String {
     format!("{}_frame", object_name)
 }
 
+pub fn get_repair_const_name(conversion_name: &str) -> String {
+    format!("{}_repair_const", conversion_name)
+}
+
 pub fn on_frame_assignment<'a, F>(
     lhs_name: &str,
     rhs_name: &str,
@@ -213,7 +227,7 @@ where
 
     let repair_const = Rc::new(z3::ast::Int::new_const(
         solver.get_context(),
-        format!("{}_repair_const", conversion_name),
+        get_repair_const_name(&conversion_name),
     ));
     frame_repair_consts.push(repair_const.clone());
 
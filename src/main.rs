@@ -1,39 +1,207 @@
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use constraints::COLUMNS_PER_OBJECT;
 use lstsq::Lstsq;
 use nalgebra::{DMatrix, DVector};
 
 use crate::constraints::constraint_system_to_linear_system;
 
+mod cache;
+mod commands;
 mod constraints;
 mod frames;
+mod signatures;
 mod types;
 mod util;
 mod walker;
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     // The path to the directory containing compile_commands.json.
     #[arg(short, long)]
-    compile_commands_directory: String,
+    compile_commands_directory: Option<String>,
 
     // Whether to output the CSV of the system of linear equations.
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     show_equations: bool,
+
+    // The path to a JSON file declaring unit signatures of library functions.
+    #[arg(long, global = true)]
+    signature_database: Option<String>,
+
+    // Whether to solve all translation units as one program instead of one at a time.
+    #[arg(short, long, global = true)]
+    whole_program: bool,
+
+    // A named set of preprocessor definitions to analyze under, e.g. `sitl=SITL,USE_GPS_TIME`.
+    // May be given several times, in which case diagnostics list the configurations they occur in.
+    #[arg(long, global = true)]
+    configuration: Vec<String>,
+
+    // Rewrites paths in the compilation database that start with OLD to start with NEW instead.
+    #[arg(long, value_name = "OLD=NEW", global = true)]
+    path_map: Vec<String>,
+
+    // Only analyzes source files matching one of these globs, e.g. `src/**/*.cc`.
+    #[arg(long, global = true)]
+    include: Vec<String>,
+
+    // Skips source files and headers matching any of these globs, e.g. `third_party/**`.
+    #[arg(long, global = true)]
+    exclude: Vec<String>,
+
+    // Only analyzes headers whose path matches this regex. All headers are analyzed by default.
+    #[arg(long, global = true)]
+    header_filter: Option<String>,
+
+    // Whether to analyze code in system headers as well.
+    #[arg(long, global = true)]
+    system_headers: bool,
+
+    // The number of worker processes analyzing translation units in parallel. Each has its own
+    // libclang and z3 instances, since libclang allows only one instance per process.
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+
+    // Whether to analyze translation units that clang reports errors in, whose ASTs may be
+    // incomplete. They are skipped by default.
+    #[arg(long, global = true)]
+    analyze_with_errors: bool,
+
+    // A directory to cache the walk of each translation unit in, so that later runs do not parse
+    // or walk translation units whose files have not changed. Not used in whole-program mode.
+    #[arg(long, global = true)]
+    cache_directory: Option<PathBuf>,
+
+    // Used by worker processes: the shard of the compile commands to analyze, as INDEX/COUNT.
+    #[arg(long, hide = true)]
+    worker_shard: Option<String>,
+
+    // Used by worker processes: the file to write their diagnostics to.
+    #[arg(long, hide = true)]
+    worker_output: Option<PathBuf>,
 }
 
+#[derive(Subcommand)]
+enum Command {
+    // Analyzes one file without a compilation database, e.g. `check file.cc -- -std=c++20`.
+    // A file of `-` reads the source from stdin.
+    Check {
+        file: String,
+
+        // The language to compile the file as, which stdin is otherwise compiled as C++.
+        #[arg(short = 'x', long, value_parser = ["c", "c++"])]
+        language: Option<String>,
+
+        // The arguments to compile the file with.
+        #[arg(last = true)]
+        compiler_arguments: Vec<String>,
+    },
+}
+
+// A named set of preprocessor definitions to analyze the program under.
+struct Configuration {
+    name: String,
+    defines: Vec<String>,
+}
+
+// A compiler driver, the language it compiles and the flags that select its system headers.
+type Toolchain = (String, util::Language, Vec<String>);
+
+// What every translation unit is analyzed with, whatever the configuration.
+#[derive(Clone, Copy)]
+struct AnalysisOptions<'a> {
+    signature_database: &'a signatures::SignatureDatabase,
+    file_filter: &'a commands::FileFilter,
+    cache: Option<&'a cache::Cache>,
+    cli_args: &'a Cli,
+}
+
+// Diagnostics keyed by the file they apply to.
+type Diagnostics = BTreeMap<String, Vec<String>>;
+
+// The translation units that could not be analyzed, with the reason why.
+type Failures = Vec<(String, String)>;
+
+// The unit annotations of each declaration, keyed by its identity, with its display name.
+type Annotations = BTreeMap<String, (String, Vec<walker::Annotation>)>;
+
+// The diagnostics, failures and annotations of analyzing the program under a configuration.
+type AnalysisResult = (String, Diagnostics, Failures, Annotations);
+
 fn main() {
     let cli_args = Cli::parse();
 
     validate_command_line_args(&cli_args);
 
-    let db_result = clang::CompilationDatabase::from_directory(cli_args.compile_commands_directory);
-    if let Err(_) = db_result {
-        std::process::exit(1);
+    let file_filter = match commands::FileFilter::new(
+        &cli_args.include,
+        &cli_args.exclude,
+        cli_args.header_filter.as_deref(),
+        cli_args.system_headers,
+    ) {
+        Ok(file_filter) => file_filter,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut compile_commands: Vec<commands::CompileCommand> = load_compile_commands(&cli_args)
+        .into_iter()
+        .filter(|cmd| file_filter.includes_source(&cmd.filename))
+        .collect();
+
+    if let Some(shard) = &cli_args.worker_shard {
+        compile_commands = select_shard(compile_commands, shard);
+    } else if cli_args.jobs > 1 && compile_commands.len() > 1 {
+        if cli_args.whole_program {
+            eprintln!("Warning: whole-program mode analyzes translation units sequentially.");
+        } else if cli_args.command.is_some() {
+            eprintln!("Warning: the check command analyzes a single file.");
+        } else {
+            let results = run_workers(&compile_commands, cli_args.jobs.min(compile_commands.len()));
+            report(results, !cli_args.configuration.is_empty());
+            return;
+        }
     }
 
+    let signature_database = match &cli_args.signature_database {
+        Some(path) => match signatures::SignatureDatabase::from_file(path) {
+            Ok(database) => database,
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                std::process::exit(1);
+            }
+        },
+        None => signatures::SignatureDatabase::new(),
+    };
+
+    // A whole-program walk depends on every translation unit walked before it, so it is not cached.
+    let use_cache = !cli_args.whole_program;
+    if cli_args.cache_directory.is_some() && !use_cache && cli_args.worker_shard.is_none() {
+        eprintln!("Warning: the cache is not used in whole-program mode.");
+    }
+    let cache = cli_args
+        .cache_directory
+        .as_ref()
+        .filter(|_| use_cache)
+        .map(|directory| match open_cache(directory, &cli_args) {
+            Ok(cache) => cache,
+            Err(err) => {
+                eprintln!("{}: {}", directory.display(), err);
+                std::process::exit(1);
+            }
+        });
+
     let clang_inst_result = clang::Clang::new();
     if let Err(err) = clang_inst_result {
         eprintln!("libclang error: {}", err);
@@ -42,119 +210,722 @@ fn main() {
     let clang_inst = clang_inst_result.unwrap();
     let index = clang::Index::new(&clang_inst, true, true);
 
-    let sys_include_flags_result = util::get_system_include_flags("clang++");
-    if let Err(err) = sys_include_flags_result {
-        eprintln!("Could not get system include paths: {}", err);
+    let z3_config = z3::Config::new();
+    let z3_context = z3::Context::new(&z3_config);
+
+    // System include paths are queried once per toolchain, including when the query fails.
+    let mut sys_include_flags: HashMap<Toolchain, Result<Vec<String>, String>> = HashMap::new();
+
+    let configurations: Vec<Configuration> = if cli_args.configuration.is_empty() {
+        vec![Configuration {
+            name: String::new(),
+            defines: Vec::new(),
+        }]
+    } else {
+        cli_args
+            .configuration
+            .iter()
+            .map(|text| parse_configuration(text))
+            .collect()
+    };
+
+    let options = AnalysisOptions {
+        signature_database: &signature_database,
+        file_filter: &file_filter,
+        cache: cache.as_ref(),
+        cli_args: &cli_args,
+    };
+    let mut results = Vec::new();
+    for configuration in &configurations {
+        let (diagnostics, failures, annotations) = analyze(
+            &compile_commands,
+            &index,
+            &z3_context,
+            &mut sys_include_flags,
+            configuration,
+            &options,
+        );
+        results.push((
+            configuration.name.clone(),
+            diagnostics,
+            failures,
+            annotations,
+        ));
+    }
+
+    if let Some(output) = &cli_args.worker_output {
+        let written = serde_json::to_string(&results)
+            .map_err(|err| err.to_string())
+            .and_then(|json| std::fs::write(output, json).map_err(|err| err.to_string()));
+        if let Err(err) = written {
+            eprintln!("{}: {}", output.display(), err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    report(results, !cli_args.configuration.is_empty());
+}
+
+// Prints the diagnostics and the translation units that failed, exiting with an error if any did.
+fn report(mut results: Vec<AnalysisResult>, list_configurations: bool) {
+    let failures: Vec<(String, Failures)> = results
+        .iter()
+        .map(|(configuration, _, failures, _)| (configuration.clone(), failures.clone()))
+        .collect();
+
+    // Annotations are compared across every translation unit, including other workers' ones.
+    let mut annotations: BTreeMap<String, Annotations> = BTreeMap::new();
+    for (configuration, _, _, result_annotations) in &mut results {
+        merge_annotations(
+            annotations.entry(configuration.clone()).or_default(),
+            std::mem::take(result_annotations),
+        );
+    }
+    for (configuration, annotations) in annotations {
+        let mut diagnostics = Diagnostics::new();
+        report_annotation_conflicts(&annotations, &mut diagnostics);
+        results.push((
+            configuration,
+            diagnostics,
+            Failures::new(),
+            Annotations::new(),
+        ));
+    }
+
+    report_diagnostics(results, list_configurations);
+    if report_failures(&failures, list_configurations) {
         std::process::exit(1);
     }
-    let sys_include_flags = sys_include_flags_result.unwrap();
+}
 
-    let z3_config = z3::Config::new();
-    let z3_context = z3::Context::new(&z3_config);
-    let z3_solver = z3::Optimize::new(&z3_context);
+// Opens the cache of translation unit walks. Entries also depend on the options that change
+// what is analyzed and the signature database's contents.
+fn open_cache(
+    directory: &Path,
+    cli_args: &Cli,
+) -> Result<cache::Cache, Box<dyn std::error::Error>> {
+    let signature_database = match &cli_args.signature_database {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => String::new(),
+    };
+    let settings = vec![
+        signature_database,
+        cli_args.include.join("\n"),
+        cli_args.exclude.join("\n"),
+        cli_args.header_filter.clone().unwrap_or_default(),
+        cli_args.system_headers.to_string(),
+        cli_args.analyze_with_errors.to_string(),
+    ];
+    cache::Cache::new(directory, &settings)
+}
+
+// Asks a toolchain's driver for its system include paths, falling back to the host clang when the
+// driver cannot be run here.
+fn get_toolchain_include_flags(toolchain: &Toolchain) -> Result<Vec<String>, String> {
+    let (driver, language, flags) = toolchain;
+    let result = util::get_system_include_flags(driver, *language, flags).or_else(|err| {
+        eprintln!(
+            "Warning: could not run {}: {}. Using {} instead.",
+            driver,
+            err,
+            language.default_compiler()
+        );
+        util::get_system_include_flags(language.default_compiler(), *language, flags)
+    });
+    result.map_err(|err| format!("could not get system include paths: {}", err))
+}
+
+// Keeps the INDEX-th of COUNT contiguous slices of the compile commands, so that concatenating the
+// shards' results in order matches a sequential run.
+fn select_shard(
+    compile_commands: Vec<commands::CompileCommand>,
+    shard: &str,
+) -> Vec<commands::CompileCommand> {
+    let parsed = shard.split_once('/').and_then(|(index, count)| {
+        Some((index.parse::<usize>().ok()?, count.parse::<usize>().ok()?))
+    });
+    let Some((index, count)) = parsed.filter(|(index, count)| index < count) else {
+        eprintln!("Malformed worker shard {}", shard);
+        std::process::exit(1);
+    };
+
+    let range = shard_range(compile_commands.len(), index, count);
+    compile_commands
+        .into_iter()
+        .skip(range.start)
+        .take(range.len())
+        .collect()
+}
 
-    let db = db_result.unwrap();
-    for cmd in db.get_all_compile_commands().get_commands() {
-        if let Err(err) = std::env::set_current_dir(cmd.get_directory()) {
-            eprintln!("{}: {}", cmd.get_directory().display(), err);
+// The indices of the compile commands in the INDEX-th of COUNT shards.
+fn shard_range(len: usize, index: usize, count: usize) -> std::ops::Range<usize> {
+    let shard_size = len.div_ceil(count);
+    (index * shard_size).min(len)..((index + 1) * shard_size).min(len)
+}
+
+// Runs this program once per shard of the compile commands and collects the workers' results in
+// shard order. The translation units of a worker that fails are reported as failures. Each
+// worker's output is captured and printed in shard order too, so runs print the same output.
+fn run_workers(compile_commands: &[commands::CompileCommand], jobs: usize) -> Vec<AnalysisResult> {
+    let executable = match std::env::current_exe() {
+        Ok(executable) => executable,
+        Err(err) => {
+            eprintln!("Could not start workers: {}", err);
             std::process::exit(1);
         }
+    };
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+
+    let workers: Vec<(PathBuf, PathBuf, std::process::Child)> = (0..jobs)
+        .map(|index| {
+            let name = format!("scalpel-{}-{}", std::process::id(), index);
+            let output = std::env::temp_dir().join(format!("{}.json", name));
+            let log = std::env::temp_dir().join(format!("{}.log", name));
+            let child = std::fs::File::create(&log).and_then(|stdout| {
+                std::process::Command::new(&executable)
+                    .arg("--worker-shard")
+                    .arg(format!("{}/{}", index, jobs))
+                    .arg("--worker-output")
+                    .arg(&output)
+                    .args(&arguments)
+                    .stdout(stdout)
+                    .spawn()
+            });
+            match child {
+                Ok(child) => (output, log, child),
+                Err(err) => {
+                    eprintln!("Could not start workers: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        })
+        .collect();
+
+    let mut results = Vec::new();
+    for (index, (output, log, mut child)) in workers.into_iter().enumerate() {
+        let shard = &compile_commands[shard_range(compile_commands.len(), index, jobs)];
+        let status = child.wait();
+        if let Ok(printed) = std::fs::read(&log) {
+            let _ = std::io::stdout().write_all(&printed);
+        }
+        let _ = std::fs::remove_file(&log);
+        let shard_results = std::fs::read_to_string(&output)
+            .map_err(|err| err.to_string())
+            .and_then(|json| {
+                serde_json::from_str::<Vec<AnalysisResult>>(&json).map_err(|err| err.to_string())
+            });
+        let _ = std::fs::remove_file(&output);
+        let reason = match (status, shard_results) {
+            (Ok(status), Ok(shard_results)) if status.success() => {
+                results.extend(shard_results);
+                continue;
+            }
+            (Ok(status), Err(err)) if status.success() => format!("worker failed: {}", err),
+            (Ok(status), _) => format!("worker exited with {}", status),
+            (Err(err), _) => format!("worker failed: {}", err),
+        };
+        let failures = shard
+            .iter()
+            .map(|cmd| (cmd.filename.display().to_string(), reason.clone()))
+            .collect();
+        results.push((
+            String::new(),
+            Diagnostics::new(),
+            failures,
+            Annotations::new(),
+        ));
+    }
+    results
+}
+
+// Parses `name=DEFINE,DEFINE=value`. A bare name is also the one definition.
+fn parse_configuration(text: &str) -> Configuration {
+    let (name, defines) = text.split_once('=').unwrap_or((text, text));
+    Configuration {
+        name: String::from(name),
+        defines: defines
+            .split(',')
+            .filter(|define| !define.is_empty())
+            .map(String::from)
+            .collect(),
+    }
+}
 
-        let mut args = cmd.get_arguments();
-        args.append(&mut sys_include_flags.clone());
+// Analyzes every compile command under one configuration.
+fn analyze(
+    compile_commands: &[commands::CompileCommand],
+    index: &clang::Index,
+    z3_context: &z3::Context,
+    sys_include_flags: &mut HashMap<Toolchain, Result<Vec<String>, String>>,
+    configuration: &Configuration,
+    options: &AnalysisOptions,
+) -> (Diagnostics, Failures, Annotations) {
+    let AnalysisOptions {
+        signature_database,
+        file_filter,
+        cache,
+        cli_args,
+    } = *options;
+    let mut diagnostics = Diagnostics::new();
+    let mut failures = Failures::new();
+    let mut annotations = Annotations::new();
+
+    // In whole-program mode, every translation unit is walked into one context, so declarations
+    // with external linkage share their objects and frame variables across files.
+    let program_solver = z3::Optimize::new(z3_context);
+    let mut program = cli_args
+        .whole_program
+        .then(|| walker::WalkContext::new(&program_solver, signature_database, file_filter));
+
+    for cmd in compile_commands {
+        let filename = cmd.filename.display().to_string();
+        if let Err(err) = std::env::set_current_dir(&cmd.directory) {
+            eprintln!("{}: {}", cmd.directory.display(), err);
+            failures.push((filename, format!("{}: {}", cmd.directory.display(), err)));
+            continue;
+        }
+
+        let mut args = cmd.arguments.clone();
+        let language = util::detect_language(&args, &cmd.filename);
+        let toolchain: Toolchain = (
+            util::get_compiler_driver(&args)
+                .unwrap_or(language.default_compiler())
+                .to_string(),
+            language,
+            util::get_toolchain_flags(&args),
+        );
+        if let Entry::Vacant(entry) = sys_include_flags.entry(toolchain.clone()) {
+            let flags = get_toolchain_include_flags(&toolchain);
+            if let Err(err) = &flags {
+                eprintln!("{}: {}", toolchain.0, err);
+            }
+            entry.insert(flags);
+        }
+        match &sys_include_flags[&toolchain] {
+            Ok(flags) => args.extend(flags.iter().cloned()),
+            Err(err) => {
+                failures.push((filename, err.clone()));
+                continue;
+            }
+        }
+        args.extend(
+            configuration
+                .defines
+                .iter()
+                .map(|define| format!("-D{}", define)),
+        );
         args = args
             .into_iter()
             .filter(|name| {
                 name != &cmd
-                    .get_filename()
+                    .filename
                     .file_name()
                     .unwrap()
                     .to_string_lossy()
                     .to_string()
+                    && Path::new(name) != cmd.filename
             })
             .collect();
 
-        let mut parser = index.parser(cmd.get_filename());
-        let parser = parser.arguments(&args);
-        let tu_result = parser.parse();
-        if let Err(err) = tu_result {
-            eprintln!("{}: {}", cmd.get_filename().display(), err);
-            std::process::exit(1);
+        let key = cache.map(|cache| cache.key(cmd, &args));
+        if let Some(analysis) = cache
+            .zip(key.as_deref())
+            .and_then(|(cache, key)| cache.lookup(key))
+        {
+            for diagnostic in &analysis.clang_diagnostics {
+                eprintln!("{}", diagnostic);
+            }
+            match analysis.walk {
+                Some(saved) => {
+                    let z3_solver = z3::Optimize::new(z3_context);
+                    let walk_result = walker::WalkContext::load(
+                        saved,
+                        &z3_solver,
+                        signature_database,
+                        file_filter,
+                    );
+                    solve(
+                        &walk_result,
+                        &z3_solver,
+                        cli_args.show_equations,
+                        &mut diagnostics,
+                    );
+                    merge_annotations(&mut annotations, get_annotations(&walk_result));
+                }
+                None => failures.push(skip_translation_unit(filename, analysis.errors)),
+            }
+            continue;
         }
 
-        let walk_result = walker::extract_types(&tu_result.unwrap(), &z3_solver);
+        let mut parser = index.parser(&cmd.filename);
+        // Macro definitions and expansions are needed for macro annotations and diagnostics.
+        let parser = parser.arguments(&args).detailed_preprocessing_record(true);
+        if let Some(contents) = &cmd.contents {
+            parser.unsaved(&[clang::Unsaved::new(&cmd.filename, contents)]);
+        }
+        let tu = match parser.parse() {
+            Ok(tu) => tu,
+            Err(err) => {
+                eprintln!("{}: {}", filename, err);
+                failures.push((filename, err.to_string()));
+                continue;
+            }
+        };
 
-        let (system, object_name_to_colums) =
-            constraint_system_to_linear_system(&walk_result.constraints, cli_args.show_equations);
+        let mut clang_diagnostics = Vec::new();
+        let mut errors = 0;
+        for diagnostic in tu.get_diagnostics() {
+            if diagnostic.get_severity() >= clang::diagnostic::Severity::Warning {
+                eprintln!("{}", diagnostic);
+                clang_diagnostics.push(diagnostic.to_string());
+            }
+            if diagnostic.get_severity() >= clang::diagnostic::Severity::Error {
+                errors += 1;
+            }
+        }
+        let store = |walk: Option<walker::SavedWalk>| {
+            let (Some(cache), Some(key)) = (cache, &key) else {
+                return;
+            };
+            let analysis = cache::Analysis {
+                clang_diagnostics: clang_diagnostics.clone(),
+                errors,
+                walk,
+            };
+            if let Err(err) = cache.store(key, &tu, cmd, analysis) {
+                eprintln!(
+                    "Warning: could not cache {}: {}",
+                    cmd.filename.display(),
+                    err
+                );
+            }
+        };
+        if errors > 0 && !cli_args.analyze_with_errors {
+            store(None);
+            failures.push(skip_translation_unit(filename, errors));
+            continue;
+        }
 
-        let mut a = Vec::<Vec<f64>>::new();
-        for row in &system {
-            let v = &row[0..row.len() - 1].to_vec();
-            a.push(v.clone());
+        match program.as_mut() {
+            Some(program) => program.walk(&tu),
+            None => {
+                let z3_solver = z3::Optimize::new(z3_context);
+                let walk_result =
+                    walker::extract_types(&tu, &z3_solver, signature_database, file_filter);
+                // Saved before solving, which adds its objective to the solver.
+                if cache.is_some() {
+                    store(Some(walk_result.save()));
+                }
+                solve(
+                    &walk_result,
+                    &z3_solver,
+                    cli_args.show_equations,
+                    &mut diagnostics,
+                );
+                merge_annotations(&mut annotations, get_annotations(&walk_result));
+            }
         }
+    }
 
-        let mut b = Vec::<f64>::new();
-        for row in &system {
-            b.push(*row.last().unwrap());
+    if let Some(program) = &program {
+        solve(
+            program,
+            &program_solver,
+            cli_args.show_equations,
+            &mut diagnostics,
+        );
+        annotations = get_annotations(program);
+    }
+    (diagnostics, failures, annotations)
+}
+
+// Reports a translation unit that is not analyzed because clang reported errors in it.
+fn skip_translation_unit(filename: String, errors: usize) -> (String, String) {
+    eprintln!(
+        "Skipping {}, which has errors. Use --analyze-with-errors to analyze it anyway.",
+        filename
+    );
+    (filename, format!("{} compile error(s)", errors))
+}
+
+// Prints a summary of the translation units that could not be analyzed, and returns whether there
+// were any.
+fn report_failures(results: &[(String, Failures)], list_configurations: bool) -> bool {
+    let failures: Vec<String> = results
+        .iter()
+        .flat_map(|(configuration, failures)| {
+            failures.iter().map(move |(file, reason)| {
+                if list_configurations && !configuration.is_empty() {
+                    format!("{}: {} [{}]", file, reason, configuration)
+                } else {
+                    format!("{}: {}", file, reason)
+                }
+            })
+        })
+        .collect();
+    if failures.is_empty() {
+        return false;
+    }
+
+    eprintln!(
+        "{} translation unit(s) could not be analyzed:",
+        failures.len()
+    );
+    for failure in &failures {
+        eprintln!("  {}", failure);
+    }
+    true
+}
+
+// Prints diagnostics grouped by file. With several configurations, each diagnostic lists the
+// configurations it occurs in.
+fn report_diagnostics(results: Vec<AnalysisResult>, list_configurations: bool) {
+    let mut merged: BTreeMap<String, Vec<(String, Vec<String>)>> = BTreeMap::new();
+    for (configuration, diagnostics, _, _) in results {
+        for (file, lines) in diagnostics {
+            let entries = merged.entry(file).or_default();
+            for line in lines {
+                match entries.iter_mut().find(|(existing, _)| *existing == line) {
+                    Some((_, configurations)) => {
+                        if !configurations.contains(&configuration) {
+                            configurations.push(configuration.clone());
+                        }
+                    }
+                    None => entries.push((line, vec![configuration.clone()])),
+                }
+            }
         }
+    }
+
+    let location = regex::Regex::new(r"file (.*?) on line (\d+) column (\d+)").unwrap();
+    for (file, mut entries) in merged {
+        // Sorted by where they occur, so that the output does not depend on the order translation
+        // units and configurations were analyzed in.
+        entries
+            .sort_by_cached_key(|(line, _)| (diagnostic_position(&location, line), line.clone()));
+        println!("{}:", file);
+        for (line, configurations) in entries {
+            if list_configurations {
+                println!("{} [{}]", line, configurations.join(", "));
+            } else {
+                println!("{}", line);
+            }
+        }
+    }
+}
+
+// Returns the file, line and column of the first location a diagnostic mentions. Diagnostics
+// without one sort last.
+fn diagnostic_position(location: &regex::Regex, diagnostic: &str) -> (bool, String, u32, u32) {
+    match location.captures(diagnostic) {
+        Some(captures) => (
+            false,
+            captures[1].to_string(),
+            captures[2].parse().unwrap_or(0),
+            captures[3].parse().unwrap_or(0),
+        ),
+        None => (true, String::new(), 0, 0),
+    }
+}
+
+// Repairs the units and frames of a walked program and reports the results.
+fn solve(
+    walk_result: &walker::WalkContext,
+    z3_solver: &z3::Optimize,
+    show_equations: bool,
+    diagnostics: &mut Diagnostics,
+) {
+    let (system, object_name_to_colums) =
+        constraint_system_to_linear_system(&walk_result.constraints, show_equations);
+
+    let mut a = Vec::<Vec<f64>>::new();
+    for row in &system {
+        let v = &row[0..row.len() - 1].to_vec();
+        a.push(v.clone());
+    }
 
-        let mut tmp_terms: Vec<constraints::Object> = walk_result
-            .tmp_terms_to_repair_contexts
-            .keys()
-            .map(|o| o.clone())
+    let mut b = Vec::<f64>::new();
+    for row in &system {
+        b.push(*row.last().unwrap());
+    }
+
+    let mut tmp_terms: Vec<constraints::Object> = walk_result
+        .tmp_terms_to_repair_contexts
+        .keys()
+        .map(|o| o.clone())
+        .collect();
+    // Sorted so that the sparsest repair found, and so the output, does not depend on hashing.
+    tmp_terms.sort_by(|a, b| a.id.cmp(&b.id));
+    let result = do_sparsest_repair(
+        &a,
+        &b,
+        &object_name_to_colums,
+        &walk_result.tmp_terms_to_repair_contexts,
+        &mut tmp_terms,
+        diagnostics,
+    );
+
+    if !result {
+        eprintln!("Program not repairable.")
+    }
+
+    report_unit_checks(
+        &a,
+        &b,
+        &object_name_to_colums,
+        &walk_result.unit_checks,
+        diagnostics,
+    );
+
+    frames::add_minimization_constraint(z3_solver, &walk_result.frame_repair_consts);
+    let frame_result = z3_solver.check(&[]);
+    if frame_result == z3::SatResult::Sat {
+        println!("Frames are satisfiable!");
+        let model = z3_solver.get_model().unwrap();
+
+        let mut repairs = Vec::new();
+        let mut conversions: Vec<_> = walk_result
+            .frame_conversion_name_to_conversion
+            .iter()
             .collect();
-        let result = do_sparsest_repair(
-            &a,
-            &b,
-            &object_name_to_colums,
-            &walk_result.tmp_terms_to_repair_contexts,
-            &mut tmp_terms,
-        );
+        conversions.sort_by(|a, b| a.0.cmp(b.0));
+        for (frame_conversion_name, conversion) in conversions {
+            let conversion = frames::Conversions::from(
+                model
+                    .get_const_interp(&(*conversion.as_ref()))
+                    .unwrap()
+                    .as_i64()
+                    .unwrap(),
+            );
+            if conversion != frames::Conversions::NoOp {
+                let repair_context = walk_result
+                    .frame_conversion_name_to_repair_context
+                    .get(frame_conversion_name)
+                    .unwrap();
 
-        if !result {
-            eprintln!("Program not repairable.")
+                repairs.push((
+                    repair_context,
+                    format!("{:?}({})", conversion, repair_context.original_expression),
+                ));
+            }
         }
+        report_repairs(repairs, diagnostics);
+    } else {
+        eprintln!("Frames not satisfiable.");
+    }
+}
+
+// Adds repairs to the diagnostics of the file they apply to.
+fn report_repairs(repairs: Vec<(&walker::RepairContext, String)>, diagnostics: &mut Diagnostics) {
+    for (context, repair) in repairs {
+        diagnostics
+            .entry(context.file.clone())
+            .or_default()
+            .push(format!("{}: {}", context.source_location, repair));
+    }
+}
 
-        frames::add_minimization_constraint(&z3_solver, &walk_result.frame_repair_consts);
-        let frame_result = z3_solver.check(&[]);
-        if frame_result == z3::SatResult::Sat {
-            println!("Frames are satisfiable!");
-            let model = z3_solver.get_model().unwrap();
+// Returns the annotations of a walked program.
+fn get_annotations(walk_result: &walker::WalkContext) -> Annotations {
+    walk_result
+        .annotations
+        .iter()
+        .map(|(id, annotations)| {
+            let name = String::from(walk_result.display_name(id));
+            (id.clone(), (name, annotations.clone()))
+        })
+        .collect()
+}
 
-            for (frame_conversion_name, conversion) in
-                &walk_result.frame_conversion_name_to_conversion
+// Adds annotations from other translation units, keeping each one once.
+fn merge_annotations(annotations: &mut Annotations, other: Annotations) {
+    for (id, (name, others)) in other {
+        let (_, existing) = annotations.entry(id).or_insert_with(|| (name, Vec::new()));
+        for annotation in others {
+            if existing
+                .iter()
+                .all(|known| known.source_location != annotation.source_location)
             {
-                let conversion = frames::Conversions::from(
-                    model
-                        .get_const_interp(&(*conversion.as_ref()))
-                        .unwrap()
-                        .as_i64()
-                        .unwrap(),
-                );
-                if conversion != frames::Conversions::NoOp {
-                    let repair_context = walk_result
-                        .frame_conversion_name_to_repair_context
-                        .get(frame_conversion_name);
-
-                    println!(
-                        "{}: {:?}({})",
-                        repair_context.unwrap().source_location,
-                        conversion,
-                        repair_context.unwrap().original_expression,
-                    )
-                }
+                existing.push(annotation);
             }
-        } else {
-            eprintln!("Frames not satisfiable.");
         }
     }
 }
 
+// Reports declarations whose redeclarations are annotated with different units. Only the first
+// annotation of each declaration is asserted.
+fn report_annotation_conflicts(annotations: &Annotations, diagnostics: &mut Diagnostics) {
+    for (name, annotations) in annotations.values() {
+        if annotations
+            .iter()
+            .all(|annotation| annotation.unit == annotations[0].unit)
+        {
+            continue;
+        }
+
+        let mut message = format!("Error: conflicting annotations for {}:", name);
+        for annotation in annotations {
+            message += &format!("\n{}: {}", annotation.source_location, annotation.text);
+        }
+        diagnostics
+            .entry(annotations[0].file.clone())
+            .or_default()
+            .push(message);
+    }
+}
+
 fn validate_command_line_args(args: &Cli) {
-    if let Err(err) = std::fs::metadata(&args.compile_commands_directory) {
-        eprintln!("{}: {}", args.compile_commands_directory, err);
-        std::process::exit(1);
+    match (&args.command, &args.compile_commands_directory) {
+        (None, None) => {
+            eprintln!("Either --compile-commands-directory or the check command is required.");
+            std::process::exit(1);
+        }
+        (None, Some(directory)) => {
+            if let Err(err) = std::fs::metadata(directory) {
+                eprintln!("{}: {}", directory, err);
+                std::process::exit(1);
+            }
+        }
+        (Some(_), _) => {}
+    }
+}
+
+// Reads the compile commands to analyze from the compilation database or the check command.
+fn load_compile_commands(args: &Cli) -> Vec<commands::CompileCommand> {
+    if let Some(Command::Check {
+        file,
+        language,
+        compiler_arguments,
+    }) = &args.command
+    {
+        return match commands::single_file(file, language.as_deref(), compiler_arguments) {
+            Ok(command) => vec![command],
+            Err(err) => {
+                eprintln!("{}: {}", file, err);
+                std::process::exit(1);
+            }
+        };
     }
+
+    let directory = args.compile_commands_directory.as_ref().unwrap();
+    let db = match clang::CompilationDatabase::from_directory(directory) {
+        Ok(db) => db,
+        Err(_) => {
+            eprintln!("{}: could not load compile_commands.json", directory);
+            std::process::exit(1);
+        }
+    };
+
+    let path_map = match commands::PathMap::from_rules(&args.path_map) {
+        Ok(path_map) => path_map,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let database_directory = std::fs::canonicalize(directory).unwrap_or(PathBuf::from(directory));
+    commands::from_database(&db, &database_directory, path_map)
 }
 
 fn do_sparsest_repair(
@@ -163,13 +934,20 @@ fn do_sparsest_repair(
     object_to_column: &HashMap<constraints::Object, i32>,
     terms_to_contexts: &HashMap<constraints::Object, walker::RepairContext>,
     temp_terms: &mut Vec<constraints::Object>,
+    diagnostics: &mut Diagnostics,
 ) -> bool {
+    // A translation unit without constraints, e.g. one whose code is all in skipped headers, has
+    // nothing to repair.
+    if system.is_empty() {
+        return true;
+    }
+
     if temp_terms.is_empty() {
         let a = DMatrix::from_fn(system.len(), system[0].len(), |i, j| system[i][j]);
         let b = DVector::from_iterator(result.len(), result.clone());
         let results = lstsq::lstsq(&a, &b, 0.001).unwrap();
         if results.residuals.abs() <= 0.01 {
-            generate_repair(results, object_to_column, terms_to_contexts);
+            generate_repair(results, object_to_column, terms_to_contexts, diagnostics);
             return true;
         } else {
             println!("Repair failed with error: {}", results.residuals.abs());
@@ -194,6 +972,7 @@ fn do_sparsest_repair(
         object_to_column,
         terms_to_contexts,
         temp_terms,
+        diagnostics,
     ) {
         return true;
     } else {
@@ -203,6 +982,7 @@ fn do_sparsest_repair(
             object_to_column,
             terms_to_contexts,
             temp_terms,
+            diagnostics,
         );
     }
 }
@@ -211,27 +991,184 @@ fn generate_repair(
     x: Lstsq<f64, nalgebra::Dyn>,
     object_to_column: &HashMap<constraints::Object, i32>,
     terms_to_contexts: &HashMap<constraints::Object, walker::RepairContext>,
+    diagnostics: &mut Diagnostics,
 ) {
     let solution = x.solution;
     // for i in 0..solution.shape().0 {
     //     println!("{}, {}", i, solution[i]);
     // }
 
-    for (obj, context) in terms_to_contexts {
+    let mut repairs = Vec::new();
+    let mut terms: Vec<_> = terms_to_contexts.iter().collect();
+    terms.sort_by(|a, b| a.0.id.cmp(&b.0.id));
+    for (obj, context) in terms {
         match object_to_column.get(obj) {
             Some(column) => {
                 let real_column = constraints::COLUMNS_PER_OBJECT * (*column as usize);
                 //println!("{} in {} at {} -> {}", obj.label, context.original_expression, context.source_location, solution[real_column]);
                 if solution[real_column].abs() > 0.00000001 {
-                    println!(
-                        "{}: (pow(10.0, {:.3}) * ({}))",
-                        context.source_location,
-                        solution[real_column] * -1.0,
-                        context.original_expression
-                    );
+                    repairs.push((
+                        context,
+                        format!(
+                            "(pow(10.0, {:.3}) * ({}))",
+                            solution[real_column] * -1.0,
+                            context.original_expression
+                        ),
+                    ));
                 }
             }
             None => eprintln!("WARNING: Unable to find column for constant {}", obj.label),
         }
     }
+    report_repairs(repairs, diagnostics);
+}
+
+fn report_unit_checks(
+    system: &[Vec<f64>],
+    result: &[f64],
+    object_to_column: &HashMap<constraints::Object, i32>,
+    checks: &[walker::UnitCheck],
+    diagnostics: &mut Diagnostics,
+) {
+    if system.is_empty() || checks.is_empty() {
+        return;
+    }
+
+    let a = DMatrix::from_fn(system.len(), system[0].len(), |i, j| system[i][j]);
+    let b = DVector::from_column_slice(result);
+    let solution = lstsq::lstsq(&a, &b, 0.001).unwrap().solution;
+    // The scalar prefix is in the first column of an object, followed by its base units.
+    let columns_of = |obj: &constraints::Object, first_column: usize| {
+        object_to_column.get(obj).map(|column| {
+            let real_column = constraints::COLUMNS_PER_OBJECT * (*column as usize);
+            (first_column..constraints::COLUMNS_PER_OBJECT)
+                .map(|offset| solution[real_column + offset])
+                .collect::<Vec<f64>>()
+        })
+    };
+    let base_units_of = |obj: &constraints::Object| columns_of(obj, 1);
+    let differ = |lhs: Option<Vec<f64>>, rhs: Option<Vec<f64>>| match (lhs, rhs) {
+        (Some(lhs), Some(rhs)) => Some(
+            lhs.iter()
+                .zip(rhs.iter())
+                .any(|(l, r)| (l - r).abs() > 0.01),
+        ),
+        _ => None,
+    };
+
+    for check in checks {
+        let violated = match &check.requirement {
+            walker::UnitRequirement::Dimensionless(obj) => {
+                base_units_of(obj).map(|units| units.iter().any(|exponent| exponent.abs() > 0.01))
+            }
+            walker::UnitRequirement::SameUnits(lhs, rhs) => {
+                differ(base_units_of(lhs), base_units_of(rhs))
+            }
+            walker::UnitRequirement::SameScaledUnits(lhs, rhs) => {
+                differ(columns_of(lhs, 0), columns_of(rhs, 0))
+            }
+        };
+
+        if violated == Some(true) {
+            diagnostics
+                .entry(check.context.file.clone())
+                .or_default()
+                .push(format!(
+                    "{}: {} in {}",
+                    check.context.source_location, check.message, check.context.original_expression
+                ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solves_translation_unit_without_constraints() {
+        let z3_config = z3::Config::new();
+        let z3_context = z3::Context::new(&z3_config);
+        let z3_solver = z3::Optimize::new(&z3_context);
+        let signature_database = signatures::SignatureDatabase::new();
+        let file_filter = commands::FileFilter::new(&[], &[], None, false).unwrap();
+        let walk_result = walker::WalkContext::new(&z3_solver, &signature_database, &file_filter);
+
+        let mut diagnostics = Diagnostics::new();
+        solve(&walk_result, &z3_solver, false, &mut diagnostics);
+        assert!(diagnostics.is_empty());
+    }
+
+    fn annotation(unit: &str, line: u32, file: &str) -> walker::Annotation {
+        walker::Annotation {
+            unit: types::parse_human_type(unit).unwrap(),
+            text: format!("length: {}", unit),
+            source_location: format!("file {} on line {} column 1", file, line),
+            file: String::from(file),
+        }
+    }
+
+    fn annotations(entries: Vec<walker::Annotation>) -> Annotations {
+        Annotations::from([(String::from("c:@length"), (String::from("length"), entries))])
+    }
+
+    #[test]
+    fn reports_conflicts_across_translation_units() {
+        let mut merged = annotations(vec![annotation("cm", 1, "a.h")]);
+        merge_annotations(&mut merged, annotations(vec![annotation("cm", 1, "a.h")]));
+        let mut diagnostics = Diagnostics::new();
+        report_annotation_conflicts(&merged, &mut diagnostics);
+        assert!(diagnostics.is_empty());
+
+        merge_annotations(&mut merged, annotations(vec![annotation("m", 4, "b.cc")]));
+        report_annotation_conflicts(&merged, &mut diagnostics);
+        assert_eq!(diagnostics["a.h"].len(), 1);
+        assert!(diagnostics["a.h"][0].contains("conflicting annotations for length"));
+        assert!(diagnostics["a.h"][0].contains("file b.cc on line 4"));
+    }
+
+    #[test]
+    fn parses_named_configuration() {
+        let configuration = parse_configuration("sitl=SITL,USE_GPS_TIME=1");
+        assert_eq!(configuration.name, "sitl");
+        assert_eq!(configuration.defines, vec!["SITL", "USE_GPS_TIME=1"]);
+    }
+
+    #[test]
+    fn parses_bare_and_empty_configurations() {
+        let configuration = parse_configuration("SITL");
+        assert_eq!(configuration.name, "SITL");
+        assert_eq!(configuration.defines, vec!["SITL"]);
+
+        let configuration = parse_configuration("hardware=");
+        assert_eq!(configuration.name, "hardware");
+        assert!(configuration.defines.is_empty());
+
+        let configuration = parse_configuration("a=B,,C");
+        assert_eq!(configuration.defines, vec!["B", "C"]);
+    }
+
+    #[test]
+    fn shards_cover_commands_in_order() {
+        for len in 0..10 {
+            for count in 1..6 {
+                let ranges: Vec<_> = (0..count)
+                    .map(|index| shard_range(len, index, count))
+                    .collect();
+                assert_eq!(ranges[0].start, 0);
+                assert_eq!(ranges[count - 1].end, len);
+                for pair in ranges.windows(2) {
+                    assert_eq!(pair[0].end, pair[1].start);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn uneven_shards_leave_later_ones_short() {
+        let ranges: Vec<_> = (0..4).map(|index| shard_range(5, index, 4)).collect();
+        assert_eq!(ranges, vec![0..2, 2..4, 4..5, 5..5]);
+        assert_eq!(shard_range(2, 1, 3), 1..2);
+        assert_eq!(shard_range(2, 2, 3), 2..2);
+    }
 }
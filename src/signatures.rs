@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use serde::Deserialize;
+
+use crate::{frames, types};
+
+// Describes how a function's result unit relates to the units of its arguments.
+#[derive(Clone, Copy, Debug)]
+pub enum Signature {
+    // The arguments must share a unit, which is also the unit of the result.
+    Uniform,
+
+    // The result has the unit of the first argument raised to a constant exponent.
+    Exponent(f64),
+
+    // The result has the unit of the base argument raised to the exponent argument.
+    Power { base: usize, exponent: usize },
+
+    // The arguments must be dimensionless, and so is the result.
+    Dimensionless,
+}
+
+// Describes how a standard container method relates to the unit of the container's elements.
+#[derive(Clone, Copy, Debug)]
+pub enum ContainerMethod {
+    // Returns an element, or an iterator or pointer to one. Any arguments are indices.
+    Element,
+
+    // Stores its last argument as an element.
+    Insert,
+}
+
+// Strips std:: and implementation namespaces such as std::__1 from a qualified name.
+fn unqualify_standard_name(qualified_name: &str) -> String {
+    let mut parts: Vec<&str> = qualified_name
+        .split("::")
+        .filter(|part| !part.starts_with("__"))
+        .collect();
+    if parts.len() > 1 && parts[0] == "std" {
+        parts.remove(0);
+    }
+    parts.join("::")
+}
+
+// Looks up the unit signature of a <cmath> or <algorithm> function. Callers check that the function
+// is the standard library's, not a user function of the same name.
+pub fn builtin_signature(qualified_name: &str) -> Option<Signature> {
+    let m: HashMap<&str, Signature> = HashMap::from([
+        ("sqrt", Signature::Exponent(0.5)),
+        ("cbrt", Signature::Exponent(1.0 / 3.0)),
+        (
+            "pow",
+            Signature::Power {
+                base: 0,
+                exponent: 1,
+            },
+        ),
+        ("hypot", Signature::Uniform),
+        ("fabs", Signature::Uniform),
+        ("abs", Signature::Uniform),
+        ("fmin", Signature::Uniform),
+        ("fmax", Signature::Uniform),
+        ("min", Signature::Uniform),
+        ("max", Signature::Uniform),
+        ("clamp", Signature::Uniform),
+        ("floor", Signature::Uniform),
+        ("ceil", Signature::Uniform),
+        ("round", Signature::Uniform),
+        ("trunc", Signature::Uniform),
+        ("exp", Signature::Dimensionless),
+        ("exp2", Signature::Dimensionless),
+        ("log", Signature::Dimensionless),
+        ("log2", Signature::Dimensionless),
+        ("log10", Signature::Dimensionless),
+    ]);
+
+    m.get(unqualify_standard_name(qualified_name).as_str())
+        .copied()
+}
+
+// Looks up a method of std::vector, std::array and the like.
+pub fn container_method(qualified_name: &str) -> Option<ContainerMethod> {
+    let name = unqualify_standard_name(qualified_name);
+    let (container, method) = name.split_once("::")?;
+    if !["vector", "array", "deque", "valarray", "span"].contains(&container) {
+        return None;
+    }
+
+    match method {
+        "at" | "front" | "back" | "data" | "begin" | "end" | "cbegin" | "cend" | "rbegin"
+        | "rend" => Some(ContainerMethod::Element),
+        "push_back" | "emplace_back" | "push_front" | "emplace_front" | "insert" | "fill" => {
+            Some(ContainerMethod::Insert)
+        }
+        _ => None,
+    }
+}
+
+// Parses a dependent exponent signature such as `power: u -> (p: nat) -> u^p`.
+pub fn parse_signature_comment(text: &str) -> Option<(&str, Signature)> {
+    let signature_regex = regex::Regex::new(
+        "([a-zA-Z_]+[a-zA-Z0-9_]*)\\s?:\\s?((?:(?:[a-zA-Z0-9_]+|\\(\\s*[a-zA-Z0-9_]+\\s?:\\s?nat\\s*\\))\\s*->\\s*)+)([a-zA-Z_]+[a-zA-Z0-9_]*)\\^([a-zA-Z_]+[a-zA-Z0-9_]*)",
+    )
+    .unwrap();
+    let nat_regex = regex::Regex::new("^\\(\\s*([a-zA-Z0-9_]+)\\s?:\\s?nat\\s*\\)$").unwrap();
+    for caps in signature_regex.captures_iter(text) {
+        let (_, [function_name, parameters, base_name, exponent_name]) = caps.extract();
+        let parameters: Vec<&str> = parameters
+            .split("->")
+            .map(|parameter| parameter.trim())
+            .filter(|parameter| !parameter.is_empty())
+            .collect();
+
+        let base = parameters
+            .iter()
+            .position(|parameter| *parameter == base_name);
+        let exponent = parameters.iter().position(|parameter| {
+            nat_regex
+                .captures(parameter)
+                .is_some_and(|caps| &caps[1] == exponent_name)
+        });
+        if let (Some(base), Some(exponent)) = (base, exponent) {
+            return Some((function_name, Signature::Power { base, exponent }));
+        }
+    }
+
+    return None;
+}
+
+// An entry of a signature database file, e.g.
+// `{"name": "geodesy::distance", "signature": "(deg, deg, deg, deg) -> m"}`.
+#[derive(Deserialize)]
+struct DatabaseEntry {
+    name: String,
+    #[serde(default)]
+    parameter_types: Option<Vec<String>>,
+    signature: String,
+    #[serde(default)]
+    frame: Option<String>,
+}
+
+// The declared signature of a library function. A unit of None leaves that position unconstrained.
+pub struct LibrarySignature {
+    // Selects an overload or template instantiation. Matches any call when absent.
+    pub parameter_types: Option<Vec<String>>,
+    pub parameters: Vec<Option<types::Type>>,
+    pub result: Option<types::Type>,
+    pub frame: Option<(frames::InertialFrames, frames::TemporalFrames)>,
+}
+
+pub struct SignatureDatabase {
+    signatures: HashMap<String, Vec<LibrarySignature>>,
+}
+
+// The parameter units and result unit of a library function.
+type UnitSignature = (Vec<Option<types::Type>>, Option<types::Type>);
+
+fn parse_unit(text: &str) -> Result<Option<types::Type>, Box<dyn Error>> {
+    if text == "_" {
+        return Ok(None);
+    }
+
+    types::parse_human_type(text)
+        .map(Some)
+        .ok_or_else(|| format!("unknown unit {}", text).into())
+}
+
+// Parses a signature such as `(deg, deg, deg, deg) -> m`.
+fn parse_library_signature(text: &str) -> Result<UnitSignature, Box<dyn Error>> {
+    let signature_regex = regex::Regex::new("^\\s*\\(([^)]*)\\)\\s*->\\s*(\\S+)\\s*$").unwrap();
+    let caps = signature_regex
+        .captures(text)
+        .ok_or_else(|| format!("malformed signature {}", text))?;
+    let (_, [parameters, result]) = caps.extract();
+
+    let parameters = parameters
+        .split(',')
+        .map(|parameter| parameter.trim())
+        .filter(|parameter| !parameter.is_empty())
+        .map(parse_unit)
+        .collect::<Result<Vec<Option<types::Type>>, Box<dyn Error>>>()?;
+    Ok((parameters, parse_unit(result)?))
+}
+
+impl SignatureDatabase {
+    pub fn new() -> SignatureDatabase {
+        SignatureDatabase {
+            signatures: HashMap::new(),
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<SignatureDatabase, Box<dyn Error>> {
+        let contents = std::fs::read_to_string(path)?;
+        let entries: Vec<DatabaseEntry> = serde_json::from_str(&contents)?;
+
+        let mut database = SignatureDatabase::new();
+        for entry in entries {
+            let (parameters, result) = parse_library_signature(&entry.signature)
+                .map_err(|err| format!("{}: {}", entry.name, err))?;
+            let frame = match &entry.frame {
+                Some(frame) => Some(
+                    frames::parse_frame_tuple(frame)
+                        .ok_or_else(|| format!("{}: malformed frame {}", entry.name, frame))?,
+                ),
+                None => None,
+            };
+
+            database
+                .signatures
+                .entry(entry.name)
+                .or_default()
+                .push(LibrarySignature {
+                    parameter_types: entry.parameter_types,
+                    parameters,
+                    result,
+                    frame,
+                });
+        }
+
+        Ok(database)
+    }
+
+    pub fn contains(&self, qualified_name: &str) -> bool {
+        self.signatures.contains_key(qualified_name)
+    }
+
+    // Finds the signature for a call, preferring an entry whose parameter types match exactly.
+    pub fn lookup(
+        &self,
+        qualified_name: &str,
+        parameter_types: &[Vec<String>],
+    ) -> Option<&LibrarySignature> {
+        let candidates = self.signatures.get(qualified_name)?;
+        candidates
+            .iter()
+            .find(|candidate| {
+                candidate.parameter_types.as_ref().is_some_and(|expected| {
+                    expected.len() == parameter_types.len()
+                        && expected
+                            .iter()
+                            .zip(parameter_types)
+                            .all(|(expected, spellings)| spellings.contains(expected))
+                })
+            })
+            .or_else(|| {
+                candidates
+                    .iter()
+                    .find(|candidate| candidate.parameter_types.is_none())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_power_signature() {
+        let (name, signature) = parse_signature_comment("power: u -> (p: nat) -> u^p").unwrap();
+        assert_eq!(name, "power");
+        assert!(matches!(
+            signature,
+            Signature::Power {
+                base: 0,
+                exponent: 1
+            }
+        ));
+    }
+
+    #[test]
+    fn parses_exponent_before_base() {
+        let (_, signature) = parse_signature_comment("raise: (n: nat) -> x -> x^n").unwrap();
+        assert!(matches!(
+            signature,
+            Signature::Power {
+                base: 1,
+                exponent: 0
+            }
+        ));
+    }
+
+    #[test]
+    fn requires_nat_binder_for_exponent() {
+        assert!(parse_signature_comment("power: u -> p -> u^p").is_none());
+        assert!(parse_signature_comment("power: u -> (q: nat) -> u^p").is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_signatures() {
+        assert!(parse_signature_comment("").is_none());
+        assert!(parse_signature_comment("power: u -> (p: nat) ->").is_none());
+        assert!(parse_signature_comment("power u -> (p: nat) -> u^p").is_none());
+        assert!(parse_signature_comment("x: m").is_none());
+    }
+}
@@ -1,6 +1,7 @@
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum SIBaseUnits {
     Second,
     Meter,
@@ -53,7 +54,7 @@ impl std::fmt::Display for SIBaseUnits {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Type {
     pub scalar_prefix: f64,
     pub si_units: [i32; NUM_BASE_UNITS],
@@ -127,6 +128,20 @@ pub fn parse_human_type(text: &str) -> Option<Type> {
                 si_units: [0, 3, 0, 0, 0, 0, 0],
             },
         ),
+        (
+            "rad",
+            Type {
+                scalar_prefix: 0.0,
+                si_units: [0, 0, 0, 0, 0, 0, 0],
+            },
+        ),
+        (
+            "deg",
+            Type {
+                scalar_prefix: -1.758_122_632_409_172,
+                si_units: [0, 0, 0, 0, 0, 0, 0],
+            },
+        ),
         (
             "s",
             Type {
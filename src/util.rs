@@ -1,13 +1,134 @@
+use std::collections::HashMap;
 use std::error::Error;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::process::Stdio;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Language {
+    C,
+    Cxx,
+}
+
+impl Language {
+    // The spelling of the language for `-x`.
+    pub fn spelling(&self) -> &'static str {
+        match self {
+            Language::C => "c",
+            Language::Cxx => "c++",
+        }
+    }
+
+    pub fn default_compiler(&self) -> &'static str {
+        match self {
+            Language::C => "clang",
+            Language::Cxx => "clang++",
+        }
+    }
+}
+
+// Detects the language of a compile command from an explicit `-x`, then the source file's
+// extension, then the compiler driver.
+pub fn detect_language(arguments: &[String], filename: &Path) -> Language {
+    let explicit = arguments
+        .iter()
+        .zip(arguments.iter().skip(1))
+        .filter_map(|(flag, value)| (flag == "-x").then_some(value.as_str()))
+        .chain(arguments.iter().filter_map(|flag| flag.strip_prefix("-x")))
+        .find(|language| !language.is_empty());
+    match explicit {
+        Some("c") | Some("c-header") => return Language::C,
+        Some(language) if language.starts_with("c++") => return Language::Cxx,
+        _ => {}
+    }
+
+    match filename
+        .extension()
+        .and_then(|extension| extension.to_str())
+    {
+        Some("c") => return Language::C,
+        Some("cc") | Some("cpp") | Some("cxx") | Some("c++") | Some("C") | Some("hpp") => {
+            return Language::Cxx
+        }
+        _ => {}
+    }
+
+    let driver = get_compiler_driver(arguments)
+        .and_then(|driver| Path::new(driver).file_name())
+        .map(|driver| driver.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if driver.ends_with("++") {
+        Language::Cxx
+    } else if driver.ends_with("gcc") || driver.ends_with("clang") || driver == "cc" {
+        Language::C
+    } else {
+        Language::Cxx
+    }
+}
+
+// Returns the compiler a compile command runs, looking through launchers such as ccache.
+pub fn get_compiler_driver(arguments: &[String]) -> Option<&str> {
+    let launchers = ["ccache", "sccache", "distcc"];
+    arguments
+        .iter()
+        .find(|argument| {
+            !Path::new(argument)
+                .file_name()
+                .is_some_and(|name| launchers.contains(&name.to_string_lossy().as_ref()))
+        })
+        .map(|driver| driver.as_str())
+}
+
+// Returns the flags of a compile command that change where its compiler finds system headers.
+pub fn get_toolchain_flags(arguments: &[String]) -> Vec<String> {
+    let separate = [
+        "-target",
+        "--sysroot",
+        "-isysroot",
+        "-isystem",
+        "--gcc-toolchain",
+    ];
+    let joined = [
+        "--target=",
+        "--sysroot=",
+        "-isysroot",
+        "-isystem",
+        "--gcc-toolchain=",
+        "-stdlib=",
+        "-nostdinc",
+    ];
+    // Only the -m flags that select a target's word size also select its library directories.
+    let exact = ["-m16", "-m32", "-m64", "-mx32"];
+
+    let mut flags = Vec::new();
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        if separate.contains(&argument.as_str()) {
+            flags.push(argument.clone());
+            flags.extend(arguments.next().cloned());
+        } else if joined.iter().any(|prefix| argument.starts_with(prefix))
+            || exact.contains(&argument.as_str())
+        {
+            flags.push(argument.clone());
+        }
+    }
+    flags
+}
+
 // See https://stackoverflow.com/questions/11946294/dump-include-paths-from-g/11946295#11946295.
-pub fn get_system_include_paths(compiler: &str) -> Result<Vec<String>, Box<dyn Error>> {
+pub fn get_system_include_paths(
+    compiler: &str,
+    language: Language,
+    toolchain_flags: &[String],
+) -> Result<Vec<String>, Box<dyn Error>> {
     let cmd = Command::new(compiler)
-        .args(["-E", "-x", "c++", "-", "-v"])
+        .args(toolchain_flags)
+        .args(["-E", "-x", language.spelling(), "-", "-v"])
         .stdin(Stdio::null())
         .output()?;
+    if !cmd.status.success() {
+        return Err(format!("{} exited with {}", compiler, cmd.status).into());
+    }
 
     let output_str = String::from_utf8(cmd.stderr)?;
     let mut in_sys_include = false;
@@ -28,11 +149,22 @@ pub fn get_system_include_paths(compiler: &str) -> Result<Vec<String>, Box<dyn E
     Ok(include_path)
 }
 
-pub fn get_system_include_flags(compiler: &str) -> Result<Vec<String>, Box<dyn Error>> {
-    let search_paths = get_system_include_paths(compiler)?;
+pub fn get_system_include_flags(
+    compiler: &str,
+    language: Language,
+    toolchain_flags: &[String],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let search_paths = get_system_include_paths(compiler, language, toolchain_flags)?;
+    // Passed as system directories, so that clang keeps their warnings quiet and searches them after
+    // the command's own include paths, like the compiler would.
     Ok(search_paths
         .iter()
-        .map(|path| "-I".to_owned() + path.split_whitespace().collect::<Vec<&str>>()[0])
+        .flat_map(|path| {
+            [
+                "-isystem".to_owned(),
+                path.split_whitespace().collect::<Vec<&str>>()[0].to_owned(),
+            ]
+        })
         .collect())
 }
 
@@ -72,16 +204,42 @@ pub fn get_initialization<'a>(definition: &'a clang::Entity) -> Option<clang::En
         return None;
     }
 
-    let children = definition.get_children();
-    if children.len() != 1 {
+    // Type references such as `Vec3` in `Vec3* p = &v;` precede the initializer.
+    let expressions: Vec<clang::Entity> = definition
+        .get_children()
+        .into_iter()
+        .filter(|child| child.is_expression())
+        .collect();
+
+    // The size of an array is an expression as well, so only an initializer list initializes it.
+    if is_array(definition) {
+        return expressions
+            .last()
+            .filter(|e| e.get_kind() == clang::EntityKind::InitListExpr)
+            .copied();
+    }
+
+    if expressions.len() != 1 {
         return None;
     }
 
-    return Some(children[0]);
+    return Some(expressions[0]);
+}
+
+pub fn is_array(declaration: &clang::Entity) -> bool {
+    declaration.get_type().is_some_and(|t| {
+        matches!(
+            t.get_canonical_type().get_kind(),
+            clang::TypeKind::ConstantArray
+                | clang::TypeKind::IncompleteArray
+                | clang::TypeKind::VariableArray
+                | clang::TypeKind::DependentSizedArray
+        )
+    })
 }
 
 pub fn has_initialization(definition: &clang::Entity) -> bool {
-    return definition.is_definition() && definition.get_children().len() == 1;
+    return get_initialization(definition).is_some();
 }
 
 pub fn get_entity_spelling(entity: &clang::Entity) -> Option<String> {
@@ -107,28 +265,458 @@ pub fn spell_source_location(entity: &clang::Entity) -> String {
         .unwrap_or(String::from("Unknown location"))
 }
 
+pub fn get_file_name(entity: &clang::Entity) -> String {
+    entity
+        .get_location()
+        .map(|location| location.get_presumed_location().0)
+        .unwrap_or(String::from("Unknown file"))
+}
+
+// Returns the `//` comment that ends a line of code, skipping slashes inside string and character
+// literals such as `"http://"`.
+fn get_line_comment(text: &str) -> Option<&str> {
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            // A quote after a digit separates digits, as in `1'000`.
+            None if c == '"'
+                || (c == '\'' && !text[..index].ends_with(|p: char| p.is_ascii_digit())) =>
+            {
+                quote = Some(c)
+            }
+            None if text[index..].starts_with("//") => return Some(&text[index..]),
+            None => {}
+        }
+    }
+    None
+}
+
+// Returns the `///` comment on a macro definition's line or on the line above it. Comments are not
+// attached to macros, so they are read from the source file.
+pub fn get_macro_comment(
+    definition: &clang::Entity,
+    sources: &mut HashMap<PathBuf, String>,
+) -> Option<String> {
+    let location = definition.get_location()?.get_file_location();
+    let path = location.file?.get_path();
+    if !sources.contains_key(&path) {
+        sources.insert(path.clone(), std::fs::read_to_string(&path).ok()?);
+    }
+    let lines: Vec<&str> = sources[&path].lines().collect();
+
+    let line = location.line as usize;
+    let trailing = lines
+        .get(line.checked_sub(1)?)
+        .and_then(|text| get_line_comment(text))
+        .and_then(|comment| comment.strip_prefix("///"));
+    let preceding = line
+        .checked_sub(2)
+        .and_then(|index| lines.get(index))
+        .and_then(|text| text.trim_start().strip_prefix("///"));
+    trailing
+        .or(preceding)
+        .map(|comment| comment.trim().to_string())
+}
+
 pub fn get_rhs<'a>(entity: &'a clang::Entity) -> Option<clang::Entity<'a>> {
-    entity.get_child(0)
+    get_initialization(entity)
 }
 
+// Returns the operator of a binary or compound assignment operator, e.g. `*=`.
 pub fn get_binary_operator(entity: &clang::Entity) -> Option<String> {
-    let left_offset = entity
-        .get_child(0)
-        .iter()
-        .map(|child| child.get_range().and_then(|r| Some(r.tokenize().len())))
-        .fold(Some(0), |acc, elt| match (acc, elt) {
-            (Some(acc), Some(elt)) => Some(acc + elt),
+    // Newer versions of libclang spell binary operators by their opcode.
+    if let Some(name) = entity.get_name() {
+        if !name.is_empty() && name.chars().all(|c| "+-*/%<>=!&|^".contains(c)) {
+            return Some(name);
+        }
+    }
+
+    // Otherwise the operator is the token at the cursor's location, which is where the operator is
+    // spelled, even in a macro's body.
+    let is_punctuation =
+        |token: &clang::token::Token| token.get_kind() == clang::token::TokenKind::Punctuation;
+    let spelling = entity.get_location()?.get_spelling_location();
+    if let Some(file) = spelling.file {
+        let range = clang::source::SourceRange::new(
+            file.get_offset_location(spelling.offset),
+            file.get_offset_location(spelling.offset + 1),
+        );
+        if let Some(operator) = range.tokenize().into_iter().next().filter(is_punctuation) {
+            return Some(operator.get_spelling());
+        }
+    }
+
+    // Failing that, it is the first punctuation token after the extent of the lhs.
+    let lhs_end = entity
+        .get_child(0)?
+        .get_range()?
+        .get_end()
+        .get_file_location();
+    entity
+        .get_range()?
+        .tokenize()
+        .into_iter()
+        .find(|token| {
+            is_punctuation(token)
+                && token.get_location().get_file_location().offset >= lhs_end.offset
+        })
+        .map(|token| token.get_spelling())
+}
+
+// Returns the operator of a prefix unary operator such as `!x`, which is its first token.
+pub fn get_prefix_operator(entity: &clang::Entity) -> Option<String> {
+    let token = entity.get_range()?.tokenize().into_iter().next()?;
+    (token.get_kind() == clang::token::TokenKind::Punctuation).then(|| token.get_spelling())
+}
+
+pub fn get_qualified_name(entity: &clang::Entity) -> String {
+    let mut names = Vec::<String>::new();
+    let mut current = Some(*entity);
+    while let Some(e) = current {
+        if e.get_kind() == clang::EntityKind::TranslationUnit {
+            break;
+        }
+        if let Some(name) = e.get_name() {
+            names.push(name);
+        }
+        current = e.get_semantic_parent();
+    }
+    names.reverse();
+    names.join("::")
+}
+
+// Spells a name for reports: functions with their parameter types, which tells overloads apart,
+// and template specializations with their arguments.
+pub fn get_readable_name(entity: &clang::Entity) -> Option<String> {
+    match entity.get_kind() {
+        clang::EntityKind::Namespace => Some(
+            entity
+                .get_name()
+                .unwrap_or(String::from("(anonymous namespace)")),
+        ),
+        _ => entity.get_display_name().filter(|name| !name.is_empty()),
+    }
+}
+
+// Like get_qualified_name, but with each scope spelled by get_readable_name, e.g. `n1::C::cm()`.
+pub fn get_readable_qualified_name(entity: &clang::Entity) -> String {
+    let mut names = Vec::<String>::new();
+    let mut current = Some(*entity);
+    while let Some(e) = current {
+        if e.get_kind() == clang::EntityKind::TranslationUnit {
+            break;
+        }
+        if let Some(name) = get_readable_name(&e) {
+            names.push(name);
+        }
+        current = e.get_semantic_parent();
+    }
+    names.reverse();
+    names.join("::")
+}
+
+pub fn evaluate_number(entity: &clang::Entity) -> Option<f64> {
+    match entity.evaluate() {
+        Some(clang::EvaluationResult::Float(f)) => Some(f),
+        Some(clang::EvaluationResult::SignedInteger(i)) => Some(i as f64),
+        Some(clang::EvaluationResult::UnsignedInteger(u)) => Some(u as f64),
+        _ => None,
+    }
+}
+
+// Spells each parameter type of a function both as written and in canonical form.
+pub fn get_parameter_type_spellings(function: &clang::Entity) -> Vec<Vec<String>> {
+    function
+        .get_type()
+        .and_then(|t| t.get_argument_types())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|t| {
+            vec![
+                t.get_display_name(),
+                t.get_canonical_type().get_display_name(),
+            ]
+        })
+        .collect()
+}
+
+// Returns the operator of a call to an overloaded operator, e.g. `+` for `operator+`.
+pub fn get_overloaded_operator(call: &clang::Entity) -> Option<String> {
+    let name = call.get_reference()?.get_name()?;
+    let operator = name.strip_prefix("operator")?.trim();
+    if operator.is_empty()
+        || operator
+            .chars()
+            .any(|c| c.is_alphanumeric() || c == '_' || c == '"')
+    {
+        // Conversion operators and user-defined literals are not arithmetic.
+        return None;
+    }
+
+    Some(String::from(operator))
+}
+
+pub fn is_alias_type(entity: &clang::Entity) -> bool {
+    entity.get_type().is_some_and(|t| {
+        matches!(
+            t.get_canonical_type().get_kind(),
+            clang::TypeKind::LValueReference
+                | clang::TypeKind::RValueReference
+                | clang::TypeKind::Pointer
+        )
+    })
+}
+
+// Whether an expression has an integer or floating point type.
+pub fn is_arithmetic(expression: &clang::Entity) -> bool {
+    expression.get_type().is_some_and(|t| {
+        let t = t.get_canonical_type();
+        t.is_integer()
+            || matches!(
+                t.get_kind(),
+                clang::TypeKind::Float
+                    | clang::TypeKind::Double
+                    | clang::TypeKind::LongDouble
+                    | clang::TypeKind::Float128
+                    | clang::TypeKind::Half
+                    | clang::TypeKind::Float16
+            )
+    })
+}
+
+// Returns the object expression of a member call such as `v` in `v.at(i)`.
+pub fn get_member_call_object<'a>(call: &clang::Entity<'a>) -> Option<clang::Entity<'a>> {
+    let mut callee = call.get_child(0)?;
+    while callee.is_unexposed() {
+        callee = callee.get_child(0)?;
+    }
+    if callee.get_kind() != clang::EntityKind::MemberRefExpr {
+        return None;
+    }
+
+    callee.get_child(0)
+}
+
+pub fn is_type_declaration(entity: &clang::Entity) -> bool {
+    [
+        clang::EntityKind::TypedefDecl,
+        clang::EntityKind::TypeAliasDecl,
+        clang::EntityKind::StructDecl,
+        clang::EntityKind::ClassDecl,
+    ]
+    .contains(&entity.get_kind())
+}
+
+// Returns the class template a type such as `Filter<Meters>` specializes, with its arguments as
+// spelled, so that typedefs passed as arguments are kept.
+pub fn get_class_specialization(t: clang::Type) -> Option<(clang::Entity, Vec<String>)> {
+    let template = t.get_declaration()?.get_template()?;
+    let arguments = t
+        .get_template_argument_types()?
+        .into_iter()
+        .map(|argument| argument.map(|a| a.get_display_name()))
+        .collect::<Option<Vec<String>>>()?;
+    Some((template, arguments))
+}
+
+// Returns the function template a function such as `scale<Meters>` specializes, with its arguments.
+pub fn get_function_specialization<'a>(
+    function: &clang::Entity<'a>,
+) -> Option<(clang::Entity<'a>, Vec<String>)> {
+    let template = function.get_template()?;
+    if template.get_kind() != clang::EntityKind::FunctionTemplate {
+        return None;
+    }
+    let arguments = function
+        .get_template_arguments()?
+        .into_iter()
+        .map(|argument| match argument {
+            clang::TemplateArgument::Type(t) => Some(t.get_display_name()),
+            clang::TemplateArgument::Integral(i, _) => Some(i.to_string()),
             _ => None,
-        });
+        })
+        .collect::<Option<Vec<String>>>()?;
+    Some((template, arguments))
+}
+
+// Scopes whose members are declarations, e.g. fields, rather than code.
+pub fn is_declaration_scope(entity: &clang::Entity) -> bool {
+    [
+        clang::EntityKind::Namespace,
+        clang::EntityKind::LinkageSpec,
+        clang::EntityKind::StructDecl,
+        clang::EntityKind::ClassDecl,
+        clang::EntityKind::UnionDecl,
+        clang::EntityKind::ClassTemplate,
+        clang::EntityKind::ClassTemplatePartialSpecialization,
+    ]
+    .contains(&entity.get_kind())
+}
+
+// Class and function templates, and members of class templates defined outside the class.
+pub fn is_template_definition(entity: &clang::Entity) -> bool {
+    [
+        clang::EntityKind::ClassTemplate,
+        clang::EntityKind::ClassTemplatePartialSpecialization,
+        clang::EntityKind::FunctionTemplate,
+    ]
+    .contains(&entity.get_kind())
+        || entity
+            .get_semantic_parent()
+            .is_some_and(|parent| parent.get_kind() == clang::EntityKind::ClassTemplate)
+}
+
+// Whether a member reference is through `this`, explicitly or implicitly.
+pub fn refers_to_this(member_reference: &clang::Entity) -> bool {
+    match member_reference.get_child(0) {
+        Some(base) => base.get_kind() == clang::EntityKind::ThisExpr,
+        None => true,
+    }
+}
+
+// Whether a member expression such as `pose.lat` or `p->lat` names a field declared outside system
+// headers.
+// Such fields are objects of their own, which also keeps the members of a union apart.
+pub fn is_user_field_reference(member_reference: &clang::Entity) -> bool {
+    member_reference.get_reference().is_some_and(|member| {
+        member.get_kind() == clang::EntityKind::FieldDecl && !member.is_in_system_header()
+    })
+}
 
-    if left_offset.is_none() {
+// Returns the fields of a declaration whose type is a struct or union defined outside system headers.
+pub fn get_record_fields<'a>(declaration: &clang::Entity<'a>) -> Option<Vec<clang::Entity<'a>>> {
+    let record_type = declaration.get_type()?.get_canonical_type();
+    if record_type.get_kind() != clang::TypeKind::Record
+        || record_type
+            .get_declaration()
+            .is_some_and(|record| record.is_in_system_header())
+    {
         return None;
     }
+    record_type.get_fields()
+}
+
+// Whether a function is declared by the standard library: in namespace std, or at global scope in
+// a system header like the C library's `log`.
+pub fn is_standard_library_function(function: &clang::Entity) -> bool {
+    function.is_in_system_header()
+        && (get_qualified_name(function).starts_with("std::")
+            || function
+                .get_semantic_parent()
+                .is_some_and(|parent| parent.get_kind() == clang::EntityKind::TranslationUnit))
+}
 
-    let entity_tokens = entity.get_range().and_then(|r| Some(r.tokenize()));
-    if entity_tokens.is_none() {
+// Whether a class is the closure type of a lambda, which has no name of its own.
+pub fn is_lambda_class(entity: &clang::Entity) -> bool {
+    entity.get_kind() == clang::EntityKind::ClassDecl
+        && entity
+            .get_display_name()
+            .is_none_or(|name| name.is_empty() || name.starts_with("(lambda"))
+}
+
+pub fn is_function(entity: &clang::Entity) -> bool {
+    [
+        clang::EntityKind::FunctionDecl,
+        clang::EntityKind::Method,
+        clang::EntityKind::Constructor,
+        clang::EntityKind::ConversionFunction,
+        clang::EntityKind::FunctionTemplate,
+    ]
+    .contains(&entity.get_kind())
+}
+
+// Returns the default member initializer of a field or the default argument of a parameter.
+pub fn get_default_initialization<'a>(declaration: &'a clang::Entity) -> Option<clang::Entity<'a>> {
+    // The width of a bit field and the size of an array are expressions as well.
+    if declaration.is_bit_field() || is_array(declaration) {
         return None;
     }
 
-    return Some(entity_tokens.unwrap()[left_offset.unwrap()].get_spelling());
+    declaration
+        .get_children()
+        .into_iter()
+        .rev()
+        .find(|child| child.is_expression())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Only one instance of clang may exist at a time.
+    static CLANG: Mutex<()> = Mutex::new(());
+
+    // Parses a function body and returns the operator of each binary operator in it, outermost
+    // first.
+    fn binary_operators(body: &str) -> Vec<Option<String>> {
+        let _guard = CLANG.lock().unwrap_or_else(|error| error.into_inner());
+        let clang = clang::Clang::new().unwrap();
+        let index = clang::Index::new(&clang, false, false);
+        let source = format!(
+            "#define SCALE(x) ((x) * 100)\n\
+             #define HALF 0.5\n\
+             #define SUM(a, b) ((a) + (b))\n\
+             void f(double x, double y) {{ {} }}\n",
+            body
+        );
+        let tu = index
+            .parser("test.cc")
+            .unsaved(&[clang::Unsaved::new("test.cc", source)])
+            .parse()
+            .unwrap();
+        let mut operators = Vec::new();
+        tu.get_entity().visit_children(|entity, _| {
+            if [
+                clang::EntityKind::BinaryOperator,
+                clang::EntityKind::CompoundAssignOperator,
+            ]
+            .contains(&entity.get_kind())
+                && entity.is_in_main_file()
+            {
+                operators.push(get_binary_operator(&entity));
+            }
+            clang::EntityVisitResult::Recurse
+        });
+        operators
+    }
+
+    fn some(operators: &[&str]) -> Vec<Option<String>> {
+        operators
+            .iter()
+            .map(|operator| Some(String::from(*operator)))
+            .collect()
+    }
+
+    #[test]
+    fn finds_operator_after_macro_operand() {
+        assert_eq!(
+            binary_operators("x = SCALE(x) - y;"),
+            some(&["=", "-", "*"])
+        );
+        assert_eq!(binary_operators("x = y + HALF;"), some(&["=", "+"]));
+        assert_eq!(binary_operators("x -= SCALE(y);"), some(&["-=", "*"]));
+    }
+
+    #[test]
+    fn finds_operator_after_parentheses_and_casts() {
+        assert_eq!(
+            binary_operators("x = ((double)(int)(x * y)) / (y - x);"),
+            some(&["=", "/", "*", "-"])
+        );
+        assert_eq!(
+            binary_operators("bool b = (x < y) == (y > x);"),
+            some(&["==", "<", ">"])
+        );
+    }
+
+    #[test]
+    fn finds_operator_in_macro_body() {
+        assert_eq!(binary_operators("x = SUM(x, y);"), some(&["=", "+"]));
+    }
 }
@@ -1,20 +1,121 @@
-use crate::constraints::assert_literal;
 use crate::util::*;
-use crate::{constraints, frames, types};
+use crate::{commands, constraints, frames, signatures, types};
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::rc::Rc;
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct RepairContext {
     pub source_location: String,
+    pub file: String,
     pub original_expression: String,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub enum UnitRequirement {
+    // The object must not have any base units.
+    Dimensionless(constraints::Object),
+
+    // Both objects must have the same base units.
+    SameUnits(constraints::Object, constraints::Object),
+
+    // Both objects must have the same base units and scalar prefix, e.g. m rather than cm.
+    SameScaledUnits(constraints::Object, constraints::Object),
+}
+
+// A requirement that is checked against the solved system instead of being added to it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UnitCheck {
+    pub requirement: UnitRequirement,
+    pub message: String,
+    pub context: RepairContext,
+}
+
+// A unit annotation and where it was written.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Annotation {
+    pub unit: types::Type,
+    pub text: String,
+    pub source_location: String,
+    pub file: String,
+}
+
+// A macro invocation in the translation unit being walked, spanning file offsets start to end.
+struct MacroExpansion {
+    file: String,
+    start: u32,
+    end: u32,
+    name: String,
+    invocation: String,
+    definition_location: String,
+    unit: Option<types::Type>,
+
+    // The index of the expansion this one is an argument of, if any.
+    parent: Option<usize>,
+}
+
+// What solving a walked translation unit needs, in a form that can be saved and loaded instead of
+// walking the translation unit again.
+#[derive(Serialize, Deserialize)]
+pub struct SavedWalk {
+    constraints: Vec<Rc<constraints::Constraint>>,
+    tmp_terms_to_repair_contexts: Vec<(constraints::Object, RepairContext)>,
+    unit_checks: Vec<UnitCheck>,
+    annotations: HashMap<String, Vec<Annotation>>,
+    display_names: HashMap<String, String>,
+
+    // The solver's frame assertions in SMT-LIB.
+    frame_assertions: String,
+    frame_conversion_name_to_repair_context: HashMap<String, RepairContext>,
+}
+
 pub struct WalkContext<'a> {
     context: Vec<String>,
     pub constraints: Vec<Rc<constraints::Constraint>>,
     object_name: Option<String>,
     fresh_count: i32,
     pub tmp_terms_to_repair_contexts: HashMap<constraints::Object, RepairContext>,
+    pub unit_checks: Vec<UnitCheck>,
+
+    // Every unit annotation of each declaration, including its redeclarations.
+    pub annotations: HashMap<String, Vec<Annotation>>,
+    function_signatures: HashMap<String, signatures::Signature>,
+
+    // Maps references and pointers to the object they are bound to.
+    aliases: HashMap<String, String>,
+
+    // Callable stuff.
+    callable_parameters: HashMap<String, Vec<String>>,
+    current_function: Option<String>,
+
+    // Template stuff.
+    type_units: HashMap<String, types::Type>,
+    instantiation: Option<String>,
+    template_substitutions: HashMap<String, String>,
+    // The template substitutions of each instantiation walked.
+    instantiations: HashMap<String, HashMap<String, String>>,
+
+    // Member functions of instantiations walked so far, and the one being walked, if any.
+    walked_members: HashSet<String>,
+    walking_member: Option<String>,
+
+    // Definitions already walked, e.g. from a header another translation unit included.
+    walked_definitions: HashSet<String>,
+    instantiation_template: Option<String>,
+
+    // Macro stuff.
+    // The expansions in each file, sorted by their start offset.
+    macro_expansions: HashMap<String, Vec<MacroExpansion>>,
+    expanding_macros: HashSet<(String, u32)>,
+
+    // Maps object identities to the names shown in reports.
+    display_names: HashMap<String, String>,
+    signature_database: &'a signatures::SignatureDatabase,
+    file_filter: &'a commands::FileFilter,
+
+    // Whether each file outside the filter has `///` comments, see has_unit_comments.
+    commented_files: HashMap<PathBuf, bool>,
 
     // Frame stuff.
     z3_solver: &'a z3::Optimize<'a>,
@@ -27,14 +128,123 @@ pub struct WalkContext<'a> {
 pub fn extract_types<'a>(
     tu: &clang::TranslationUnit,
     solver: &'a z3::Optimize<'a>,
+    signature_database: &'a signatures::SignatureDatabase,
+    file_filter: &'a commands::FileFilter,
 ) -> WalkContext<'a> {
-    let root_entity = tu.get_entity();
-    let mut w = WalkContext::new(&solver);
-    root_entity.visit_children(|n, p| w.analyze_entity(n, p));
+    let mut w = WalkContext::new(solver, signature_database, file_filter);
+    w.walk(tu);
     return w;
 }
 
 impl<'a> WalkContext<'a> {
+    // Adds a translation unit's constraints. Walking several units into one context links them
+    // through the declarations they share.
+    pub fn walk(&mut self, tu: &clang::TranslationUnit) {
+        let root_entity = tu.get_entity();
+        self.collect_macros(&root_entity);
+        root_entity.visit_children(|n, p| self.analyze_entity(n, p));
+    }
+
+    // Records annotated macro definitions and all macro expansions up front, since expansions
+    // are visited after the declarations that contain them.
+    fn collect_macros(&mut self, root: &clang::Entity) {
+        self.macro_expansions.clear();
+        let mut macro_units: HashMap<String, types::Type> = HashMap::new();
+        let mut sources = HashMap::new();
+        for child in root.get_children() {
+            if child.is_in_system_header() {
+                continue;
+            }
+
+            if child.get_kind() == clang::EntityKind::MacroDefinition {
+                let (Some(name), Some(text)) =
+                    (child.get_name(), get_macro_comment(&child, &mut sources))
+                else {
+                    continue;
+                };
+                if let Some((_, unit)) = types::parse_type_comment(&text) {
+                    if let Some(id) = self.name_declaration(&child) {
+                        self.record_annotation(&id, unit, &text, &child);
+                    }
+                    macro_units.insert(name, unit);
+                }
+            } else if child.get_kind() == clang::EntityKind::MacroExpansion {
+                let (Some(range), Some(name)) = (child.get_range(), child.get_name()) else {
+                    continue;
+                };
+                let start = range.get_start().get_file_location();
+                let end = range.get_end().get_file_location();
+                let Some(file) = start.file else {
+                    continue;
+                };
+                let file = file.get_path().display().to_string();
+                let expansions = self.macro_expansions.entry(file.clone()).or_default();
+                expansions.push(MacroExpansion {
+                    file,
+                    start: start.offset,
+                    end: end.offset,
+                    unit: macro_units.get(&name).copied(),
+                    invocation: get_entity_spelling(&child).unwrap_or(name.clone()),
+                    definition_location: child
+                        .get_reference()
+                        .map(|definition| spell_source_location(&definition))
+                        .unwrap_or(String::from("Unknown location")),
+                    name,
+                    parent: None,
+                });
+            }
+        }
+
+        // Expansions nest in the arguments of others, so each one's enclosing expansion is the
+        // nearest preceding one that contains it.
+        for expansions in self.macro_expansions.values_mut() {
+            expansions.sort_by_key(|expansion| (expansion.start, std::cmp::Reverse(expansion.end)));
+            let mut enclosing: Vec<usize> = Vec::new();
+            for index in 0..expansions.len() {
+                while let Some(&outer) = enclosing.last() {
+                    if expansions[index].end <= expansions[outer].end {
+                        break;
+                    }
+                    enclosing.pop();
+                }
+                expansions[index].parent = enclosing.last().copied();
+                enclosing.push(index);
+            }
+        }
+    }
+
+    // Finds the innermost macro expansion an entity lies in. Tokens from a macro's body are
+    // located at the start of the expansion, while tokens from its arguments are located where
+    // the argument is written.
+    fn get_macro_expansion(&self, entity: &clang::Entity) -> Option<(&MacroExpansion, bool)> {
+        let range = entity.get_range()?;
+        let start = range.get_start().get_file_location();
+        let end = range.get_end().get_file_location();
+        let file = start.file?.get_path().display().to_string();
+        let expansions = self.macro_expansions.get(&file)?;
+
+        // The innermost expansion that starts before the entity either contains it, or one of the
+        // expansions enclosing it does.
+        let mut candidate = expansions
+            .partition_point(|expansion| expansion.start <= start.offset)
+            .checked_sub(1);
+        while let Some(index) = candidate {
+            let expansion = &expansions[index];
+            if end.offset <= expansion.end {
+                return Some((expansion, expansion.start == start.offset));
+            }
+            candidate = expansion.parent;
+        }
+        None
+    }
+
+    // Returns the expansion whose body an expression comes from, if any.
+    fn get_macro_body_expansion(&self, entity: &clang::Entity) -> Option<&MacroExpansion> {
+        self.get_macro_expansion(entity)
+            .filter(|(_, in_body)| *in_body)
+            .map(|(expansion, _)| expansion)
+    }
+
     fn qualify_name(&self, name: &str) -> String {
         if self.context.len() > 0 {
             self.context.join("::") + "::" + name
@@ -43,53 +253,309 @@ impl<'a> WalkContext<'a> {
         }
     }
 
+    // Builds the constraint object for an identity, labeled with its display name.
+    fn object(&self, id: &str) -> constraints::Object {
+        constraints::Object::with_label(id, self.display_name(id))
+    }
+
+    pub fn display_name<'b>(&'b self, id: &'b str) -> &'b str {
+        self.display_names
+            .get(id)
+            .map_or(id, |label| label.as_str())
+    }
+
+    // Identifies a declaration by its USR, so that every reference to it agrees on its object.
+    // Parameters are numbered within their function, since their USRs differ between redeclarations.
+    fn declaration_id(&self, declaration: &clang::Entity) -> Option<String> {
+        let declaration = declaration.get_canonical_entity();
+        let id = match declaration.get_kind() {
+            clang::EntityKind::ParmDecl => declaration
+                .get_semantic_parent()
+                .and_then(|function| {
+                    let index = function
+                        .get_arguments()?
+                        .iter()
+                        .position(|parameter| *parameter == declaration)?;
+                    Some(format!(
+                        "{}::{}",
+                        function.get_canonical_entity().get_usr()?.0,
+                        index
+                    ))
+                })
+                .or_else(|| declaration.get_usr().map(|usr| usr.0))?,
+            _ => declaration.get_usr()?.0,
+        };
+
+        // A template and the declarations inside it are distinct objects in each instantiation.
+        if let (Some(template), Some(instantiation)) =
+            (&self.instantiation_template, &self.instantiation)
+        {
+            let mut parent = Some(declaration);
+            while let Some(entity) = parent {
+                if entity.get_usr().is_some_and(|usr| usr.0 == *template) {
+                    return Some(format!("{}@{}", id, instantiation));
+                }
+                parent = entity.get_semantic_parent();
+            }
+        }
+        Some(id)
+    }
+
+    // Labels a declaration by the scopes it is declared in, e.g. `n1::C::cm()::x`, wherever it is
+    // first seen from. Inside an instantiation, the template is spelled with its arguments.
+    // Declarations inside a lambda are labeled by the walker's context, which names the lambda
+    // after its location.
+    fn declaration_label(&self, declaration: &clang::Entity, name: &str) -> String {
+        let mut names = Vec::new();
+        let mut current = Some(*declaration);
+        while let Some(entity) = current {
+            if entity.get_kind() == clang::EntityKind::TranslationUnit {
+                break;
+            }
+            if is_lambda_class(&entity) {
+                return self.qualify_name(name);
+            }
+            if self.instantiation_template.is_some()
+                && entity.get_usr().map(|usr| usr.0) == self.instantiation_template
+            {
+                names.extend(self.instantiation.clone());
+                break;
+            }
+            names.extend(get_readable_name(&entity));
+            current = entity.get_semantic_parent();
+        }
+        names.reverse();
+        names.join("::")
+    }
+
+    // Returns the identity of a named declaration and records its display name.
+    fn name_declaration(&mut self, declaration: &clang::Entity) -> Option<String> {
+        let name = declaration.get_name()?;
+        if !declaration.is_declaration() {
+            return Some(self.qualify_name(&name));
+        }
+        let label = self.declaration_label(declaration, &name);
+        let id = self.declaration_id(declaration).unwrap_or(label.clone());
+        self.display_names.entry(id.clone()).or_insert(label);
+        Some(id)
+    }
+
+    // Remembers an annotation once, even if its declaration is walked again, e.g. from a header.
+    // Records an annotation, and returns whether it is the declaration's first. Only the first is
+    // asserted, so that conflicting annotations are reported instead of making the system
+    // unsolvable.
+    fn record_annotation(
+        &mut self,
+        id: &str,
+        unit: types::Type,
+        text: &str,
+        node: &clang::Entity,
+    ) -> bool {
+        let source_location = spell_source_location(node);
+        let annotations = self.annotations.entry(String::from(id)).or_default();
+        let is_first = annotations.is_empty();
+        if annotations
+            .iter()
+            .all(|annotation| annotation.source_location != source_location)
+        {
+            annotations.push(Annotation {
+                unit,
+                text: String::from(text),
+                source_location,
+                file: get_file_name(node),
+            });
+        }
+        is_first
+    }
+
     fn fresh_variable(&mut self) -> String {
         let varname = "T".to_owned() + &self.fresh_count.to_string();
         self.fresh_count += 1;
         return varname;
     }
 
+    // A fresh variable for a truth value, such as the result of a comparison.
+    fn fresh_dimensionless_variable(&mut self) -> String {
+        let varname = self.fresh_variable();
+        self.constraints
+            .push(constraints::assert_dimensionless(Rc::new(
+                self.object(&varname),
+            )));
+        varname
+    }
+
+    // Applies a declaration's unit annotations and the unit of its declared type.
+    fn apply_declaration_units(&mut self, node: &clang::Entity) {
+        let Some(qname) = self.name_declaration(node) else {
+            return;
+        };
+        if let Some(comment) = node.get_parsed_comment() {
+            let text = get_comment_text(&comment);
+            if let Some((_, iframe, tframe)) = frames::parse_human_frame(&text) {
+                let z3_var = frames::frame_assert(&qname, (&iframe, &tframe), self.z3_solver);
+                self.object_name_to_frame_var
+                    .insert(String::from(&qname), z3_var);
+                println!("I see {} {:?} {:?}", self.object(&qname), iframe, tframe);
+            }
+            if let Some((_, signature)) = signatures::parse_signature_comment(&text) {
+                self.function_signatures
+                    .insert(get_qualified_name(node), signature);
+            }
+            if let Some((_, type_info)) = types::parse_type_comment(&text) {
+                if is_type_declaration(node) {
+                    // Declarations of this type, e.g. through a template argument, carry the unit.
+                    if let Some(t) = node.get_type() {
+                        self.type_units.insert(t.get_display_name(), type_info);
+                    }
+                }
+                if self.record_annotation(&qname, type_info, &text, node) {
+                    // A function's annotation gives the unit of its result.
+                    let annotated = if is_function(node) {
+                        self.return_object(&qname)
+                    } else {
+                        qname.clone()
+                    };
+                    let object = Rc::new(self.object(&annotated));
+                    let constraint = constraints::type_to_constraint(&type_info, object);
+                    //println!("For object {} added constraint {}", qname, constraint);
+                    self.constraints.push(constraint);
+                }
+            }
+        }
+
+        if let Some(type_info) = self.get_declared_type_unit(node) {
+            let object = Rc::new(self.object(&qname));
+            self.constraints
+                .push(constraints::type_to_constraint(&type_info, object));
+        }
+    }
+
+    // Whether the file an entity is in has any `///` comments, which unit annotations are.
+    fn has_unit_comments(&mut self, entity: &clang::Entity) -> bool {
+        let Some(file) = entity
+            .get_location()
+            .and_then(|location| location.get_file_location().file)
+        else {
+            return true;
+        };
+        let path = file.get_path();
+        *self.commented_files.entry(path.clone()).or_insert_with(|| {
+            std::fs::read_to_string(&path).map_or(true, |text| text.contains("///"))
+        })
+    }
+
     fn analyze_entity(
         &mut self,
         node: clang::Entity,
-        _parent: clang::Entity,
+        parent: clang::Entity,
     ) -> clang::EntityVisitResult {
         let context_introducers = HashSet::from([
             clang::EntityKind::ClassDecl,
+            clang::EntityKind::StructDecl,
+            clang::EntityKind::UnionDecl,
             clang::EntityKind::ClassTemplate,
             clang::EntityKind::FunctionDecl,
             clang::EntityKind::Method,
+            clang::EntityKind::Constructor,
             clang::EntityKind::FunctionTemplate,
             clang::EntityKind::Namespace,
         ]);
 
-        if let Some(qname) = node.get_name().and_then(|n| Some(self.qualify_name(&n))) {
-            if let Some(comment) = node.get_parsed_comment() {
-                let text = get_comment_text(&comment);
-                if let Some((_, iframe, tframe)) = frames::parse_human_frame(&text) {
-                    let z3_var = frames::frame_assert(&qname, (&iframe, &tframe), self.z3_solver);
-                    self.object_name_to_frame_var
-                        .insert(String::from(&qname), z3_var);
-                    println!("I see {} {:?} {:?}", qname, iframe, tframe);
-                }
-                if let Some((_, type_info)) = types::parse_type_comment(&text) {
-                    let object = Rc::new(constraints::Object::new(&qname));
-                    let constraint = constraints::type_to_constraint(&type_info, object);
-                    //println!("For object {} added constraint {}", qname, constraint);
-                    self.constraints.push(constraint);
+        // Code in filtered headers is skipped. Their declarations still carry their annotations, so
+        // headers with unit comments are searched for them, but only through declaration scopes.
+        if !self.is_analyzed(&node) {
+            if !self.has_unit_comments(&node) {
+                return clang::EntityVisitResult::Continue;
+            }
+            self.apply_declaration_units(&node);
+            return if is_declaration_scope(&node) {
+                clang::EntityVisitResult::Recurse
+            } else {
+                clang::EntityVisitResult::Continue
+            };
+        }
+        self.apply_declaration_units(&node);
+
+        self.discover_instantiation(&node);
+
+        // An annotated macro gives its unit to the outermost expression of its expansion.
+        if node.is_expression() {
+            let expansion = self.get_macro_body_expansion(&node).and_then(|expansion| {
+                Some(((expansion.file.clone(), expansion.start), expansion.unit?))
+            });
+            if let Some((key, unit)) = expansion {
+                if self.expanding_macros.insert(key.clone()) {
+                    let object = self.analyze_expression(&node, &parent);
+                    self.expanding_macros.remove(&key);
+                    if let Some(object) = &object {
+                        let object = Rc::new(self.object(object));
+                        self.constraints
+                            .push(constraints::type_to_constraint(&unit, object));
+                    }
+                    self.object_name = object;
+                    return clang::EntityVisitResult::Continue;
                 }
             }
         }
 
+        // Templates are analyzed once per instantiation instead of generically.
+        if is_template_definition(&node) && self.instantiation.is_none() {
+            return clang::EntityVisitResult::Continue;
+        }
+        if self.is_unused_member(&node) {
+            return clang::EntityVisitResult::Continue;
+        }
+
+        // In whole-program mode, definitions in shared headers are walked for the first
+        // translation unit that includes them. Walking them again would duplicate their constraints.
+        if self.is_walked_again(&node) {
+            return clang::EntityVisitResult::Continue;
+        }
+
+        // Library functions with a declared signature are typed at their call sites instead.
+        if node.is_definition() && self.signature_database.contains(&get_qualified_name(&node)) {
+            return clang::EntityVisitResult::Continue;
+        }
+
         // Handle functions, namespaces, etc.
         if node.is_definition()
             && context_introducers.contains(&node.get_kind())
-            && node.get_mangled_name().is_some()
+            && self.get_context_name(&node).is_some()
         {
-            let name = node.get_mangled_name().unwrap();
-            self.context.push(name);
-            node.visit_children(|n, p| self.analyze_entity(n, p));
-            self.context.pop();
+            let name = self.get_context_name(&node).unwrap();
+            // Out-of-line definitions are named after the scope they are declared in.
+            let saved_context = if self.instantiation.is_none()
+                && node.get_lexical_parent() != node.get_semantic_parent()
+            {
+                Some(std::mem::replace(
+                    &mut self.context,
+                    vec![get_readable_qualified_name(&node)],
+                ))
+            } else {
+                self.context.push(name);
+                None
+            };
+            let saved_function = self.current_function.clone();
+            if is_function(&node) {
+                let callable = self.name_declaration(&node).unwrap();
+                self.register_callable(&callable, &node);
+                if node.get_kind() == clang::EntityKind::Method {
+                    self.check_overrides(&node, &callable);
+                }
+                self.current_function = Some(callable);
+            }
+            if node.get_kind() == clang::EntityKind::Constructor {
+                self.walk_constructor(&node);
+            } else {
+                node.visit_children(|n, p| self.analyze_entity(n, p));
+            }
+            self.current_function = saved_function;
+            match saved_context {
+                Some(context) => self.context = context,
+                None => {
+                    self.context.pop();
+                }
+            }
             return clang::EntityVisitResult::Continue;
         } else if node.is_unexposed() {
             // Just recurse until we get to an expose expression.
@@ -104,6 +570,38 @@ impl<'a> WalkContext<'a> {
             //     node.is_declaration()
             // );
 
+            // Default member initializers and default arguments initialize like definitions do.
+            if node.get_kind() == clang::EntityKind::FieldDecl
+                || node.get_kind() == clang::EntityKind::ParmDecl
+            {
+                if let (Some(initializer), Some(lhs_object)) = (
+                    get_default_initialization(&node),
+                    self.name_declaration(&node),
+                ) {
+                    if let Some(rhs_object) = self.analyze_expression(&initializer, &node) {
+                        self.push_initialization(
+                            &lhs_object,
+                            &rhs_object,
+                            &initializer,
+                            &initializer,
+                        );
+                    }
+                }
+                self.object_name = None;
+                return clang::EntityVisitResult::Continue;
+            }
+
+            // Aggregate initialization of a struct or union initializes each of its fields.
+            if let (Some(initializer), Some(fields)) = (get_rhs(&node), get_record_fields(&node)) {
+                if node.get_kind() == clang::EntityKind::VarDecl
+                    && initializer.get_kind() == clang::EntityKind::InitListExpr
+                {
+                    self.apply_record_initialization(&initializer, &fields);
+                    self.object_name = None;
+                    return clang::EntityVisitResult::Continue;
+                }
+            }
+
             // Create constraints based on the RHS.
             if node.is_definition() && has_initialization(&node) {
                 self.object_name = None;
@@ -113,94 +611,221 @@ impl<'a> WalkContext<'a> {
                 if let None = self.object_name {
                     eprintln!(
                         "Warning: has a RHS with an unknown object name in {}.",
-                        spell_source_location(&node)
+                        self.spell_location(&node)
                     );
                     return clang::EntityVisitResult::Continue;
                 }
 
-                let lhs_object = node
-                    .get_name()
-                    .and_then(|name| Some(self.qualify_name(&name)))
-                    .unwrap_or(format!(
-                        "Unknown object in {}",
-                        spell_source_location(&node)
-                    ));
-
-                println!(
-                    "Visiting lhs {} and rhs {} in assignment.",
-                    lhs_object,
-                    self.object_name.as_ref().unwrap()
-                );
-
-                let mut count = self.fresh_count;
-                let naming_fn = || {
-                    let name = format!("T{}", count);
-                    count += 1;
-                    return name;
-                };
-
-                let frame_repair_name = frames::on_frame_assignment(
-                    &lhs_object,
-                    self.object_name.as_ref().unwrap(),
-                    self.z3_solver,
-                    &mut self.object_name_to_frame_var,
-                    &mut self.frame_conversion_name_to_conversion,
-                    &mut self.frame_repair_consts,
-                    naming_fn,
-                );
-                self.fresh_count = count;
-                self.frame_conversion_name_to_repair_context.insert(
-                    frame_repair_name,
-                    RepairContext {
-                        source_location: spell_source_location(&node),
-                        original_expression: get_rhs(&node)
-                            .and_then(|entity| get_entity_spelling(&entity))
-                            .unwrap_or(String::from("Unknown spelling")),
-                    },
-                );
+                let lhs_object = self
+                    .name_declaration(&node)
+                    .unwrap_or(format!("Unknown object in {}", self.spell_location(&node)));
 
-                let lobj = Rc::new(constraints::Object::new(&lhs_object));
-                let repair_term = self.fresh_variable();
-                let repair_constant = Rc::new(constraints::Object::new(&repair_term));
-                let robj = Rc::new(constraints::Object::new(
-                    &self.object_name.as_ref().unwrap(),
-                ));
-
-                let original_expression = get_rhs(&node)
-                    .and_then(|entity| get_entity_spelling(&entity))
-                    .unwrap_or(String::from("Unknown spelling"));
-                let source_location = spell_source_location(&node);
-                self.tmp_terms_to_repair_contexts.insert(
-                    constraints::Object::new(&repair_term),
-                    RepairContext {
-                        source_location,
-                        original_expression,
-                    },
-                );
+                // Binding a reference or pointer aliases its target rather than assigning to it.
+                // So does storing a callable in a std::function or function pointer.
+                if is_alias_type(&node)
+                    || self
+                        .callable_parameters
+                        .contains_key(self.object_name.as_ref().unwrap())
+                {
+                    let target = self.object_name.clone().unwrap();
+                    self.aliases.insert(lhs_object, target);
+                    return clang::EntityVisitResult::Continue;
+                }
 
-                let constraint = constraints::assert_repairable(lobj, robj, repair_constant);
-                self.constraints.push(constraint);
+                let rhs_object = self.object_name.clone().unwrap();
+                self.push_initialization(&lhs_object, &rhs_object, &node, &get_rhs(&node).unwrap());
 
                 return clang::EntityVisitResult::Continue;
             } else if node.get_kind() == clang::EntityKind::DeclRefExpr {
-                self.object_name = Some(
-                    self.qualify_name(&node.get_name().unwrap_or(String::from("Unknown object"))),
-                );
+                // Naming a function, e.g. to take its address, refers to it as a callable. Direct
+                // calls are typed with their CallExpr and do not visit their callee.
+                if let Some(function) = node.get_reference().filter(is_function) {
+                    if let Some(callable) = self.name_declaration(&function) {
+                        self.register_callable(&callable, &function);
+                        self.object_name = Some(callable);
+                        return clang::EntityVisitResult::Continue;
+                    }
+                }
+
+                let name = node
+                    .get_reference()
+                    .and_then(|declaration| self.name_declaration(&declaration))
+                    .unwrap_or_else(|| {
+                        self.qualify_name(
+                            &node.get_name().unwrap_or(String::from("Unknown object")),
+                        )
+                    });
+                self.object_name = Some(self.resolve_alias(name));
+                return clang::EntityVisitResult::Continue;
+            } else if node.get_kind() == clang::EntityKind::LambdaExpr {
+                self.object_name = Some(self.walk_lambda(&node));
+                return clang::EntityVisitResult::Continue;
+            } else if node.get_kind() == clang::EntityKind::ReturnStmt
+                && self.current_function.is_some()
+            {
+                if let Some(value) = node.get_child(0) {
+                    if let Some(value_object) = self.analyze_expression(&value, &node) {
+                        let callable = self.current_function.clone().unwrap();
+                        let return_object = self.return_object(&callable);
+                        self.push_repairable(&return_object, &value_object, &value);
+                    }
+                }
+                self.object_name = None;
                 return clang::EntityVisitResult::Continue;
             } else if node.get_kind() == clang::EntityKind::FloatingLiteral {
                 if let Some(clang::EvaluationResult::Float(f)) = node.evaluate() {
-                    let object_name = format!("literal {} at {}", f, spell_source_location(&node));
-                    self.constraints.push(assert_literal(f, &object_name));
+                    // Literals from a macro's body all share the location of its expansion, so
+                    // they are told apart by where they are spelled and by their value.
+                    let spelling = node
+                        .get_location()
+                        .map(|location| location.get_spelling_location());
+                    let object_name = format!(
+                        "literal:{}:{}:{}",
+                        spell_source_location(&node),
+                        spelling.map_or(0, |location| location.offset),
+                        f
+                    );
+                    self.display_names.insert(
+                        object_name.clone(),
+                        format!("literal {} at {}", f, self.spell_location(&node)),
+                    );
+                    let literal = Rc::new(self.object(&object_name));
+                    self.constraints
+                        .push(constraints::assert_literal(f, literal));
                     self.object_name = Some(object_name);
                 } else {
                     eprintln!(
                         "Warning: Could not evaluate node at {}",
-                        spell_source_location(&node)
+                        self.spell_location(&node)
                     );
                     self.object_name = None;
                 }
                 return clang::EntityVisitResult::Continue;
-            } else if node.get_kind() == clang::EntityKind::BinaryOperator {
+            } else if node.get_kind() == clang::EntityKind::CallExpr {
+                let database = self.signature_database;
+                let library_signature = node.get_reference().and_then(|callee| {
+                    database.lookup(
+                        &get_qualified_name(&callee),
+                        &get_parameter_type_spellings(&callee),
+                    )
+                });
+                if let Some(library_signature) = library_signature {
+                    self.apply_library_signature(&node, library_signature);
+                    return clang::EntityVisitResult::Continue;
+                }
+
+                let signature = node.get_reference().and_then(|callee| {
+                    let callee_name = get_qualified_name(&callee);
+                    self.get_annotated_signature(&callee).or_else(|| {
+                        is_standard_library_function(&callee)
+                            .then(|| signatures::builtin_signature(&callee_name))
+                            .flatten()
+                    })
+                });
+                if let Some(signature) = signature {
+                    self.apply_signature(&node, signature);
+                    return clang::EntityVisitResult::Continue;
+                }
+
+                let container_method = node
+                    .get_reference()
+                    .and_then(|callee| signatures::container_method(&get_qualified_name(&callee)));
+                if let (Some(method), Some(container)) =
+                    (container_method, get_member_call_object(&node))
+                {
+                    self.apply_container_method(&node, &container, method);
+                    return clang::EntityVisitResult::Continue;
+                }
+
+                if let Some(operator) = get_overloaded_operator(&node) {
+                    self.apply_overloaded_operator(&node, &operator);
+                    return clang::EntityVisitResult::Continue;
+                }
+
+                // A direct call binds its arguments to the function's parameters and has the
+                // function's result unit. Constructions are typed by the object they initialize.
+                let direct_callee = node.get_reference().filter(|callee| {
+                    is_function(callee)
+                        && ![
+                            clang::EntityKind::Constructor,
+                            clang::EntityKind::ConversionFunction,
+                        ]
+                        .contains(&callee.get_kind())
+                });
+                if let Some(function) = direct_callee {
+                    let callable = match self.get_instantiated_callee(&node, &function) {
+                        Some((template, name, pattern)) => {
+                            self.use_instantiated_function(&template, &name, &pattern)
+                        }
+                        None => self.name_declaration(&function).inspect(|callable| {
+                            self.register_callable(callable, &function);
+                        }),
+                    };
+                    if let Some(callable) = callable {
+                        if let Some(object) = get_member_call_object(&node) {
+                            self.analyze_expression(&object, &node);
+                        }
+                        let arguments = node.get_arguments().unwrap_or_default();
+                        self.apply_invocation(&node, &callable, &arguments);
+                        return clang::EntityVisitResult::Continue;
+                    }
+                }
+
+                // Calls through function pointers invoke whatever the pointer is bound to.
+                if !node
+                    .get_reference()
+                    .is_some_and(|callee| is_function(&callee))
+                {
+                    let callable = node
+                        .get_child(0)
+                        .and_then(|callee| self.analyze_expression(&callee, &node))
+                        .filter(|callable| self.callable_parameters.contains_key(callable));
+                    if let Some(callable) = callable {
+                        let arguments = node.get_arguments().unwrap_or_default();
+                        self.apply_invocation(&node, &callable, &arguments);
+                        return clang::EntityVisitResult::Continue;
+                    }
+                }
+            } else if node.get_kind() == clang::EntityKind::MemberRefExpr
+                && (refers_to_this(&node) || is_user_field_reference(&node))
+            {
+                // The base of `p->lat` or `poses[i].lat` may have constraints of its own, such as
+                // its index being dimensionless.
+                if let Some(base) = node.get_child(0).filter(|_| !refers_to_this(&node)) {
+                    self.analyze_expression(&base, &node);
+                }
+                self.object_name = self.get_member_object(&node);
+                return clang::EntityVisitResult::Continue;
+            } else if node.get_kind() == clang::EntityKind::ArraySubscriptExpr {
+                let children = node.get_children();
+                if children.len() == 2 {
+                    self.object_name = self.apply_subscript(&node, &children[0], &children[1]);
+                    return clang::EntityVisitResult::Continue;
+                }
+            } else if node.get_kind() == clang::EntityKind::UnaryOperator
+                && get_prefix_operator(&node).as_deref() == Some("!")
+            {
+                let operand = node.get_child(0);
+                let operand_object =
+                    operand.and_then(|operand| self.analyze_expression(&operand, &node));
+                // Negating a pointer tests whether it is null, whatever the unit of its target.
+                if let Some(operand_object) =
+                    operand_object.filter(|_| operand.as_ref().is_some_and(is_arithmetic))
+                {
+                    self.push_unit_check(
+                        UnitRequirement::Dimensionless(self.object(&operand_object)),
+                        String::from("operand of ! must be dimensionless"),
+                        &node,
+                    );
+                }
+                self.object_name = Some(self.fresh_dimensionless_variable());
+                return clang::EntityVisitResult::Continue;
+            } else if node.get_kind() == clang::EntityKind::ForRangeStmt {
+                self.apply_range_for(&node);
+                return clang::EntityVisitResult::Continue;
+            } else if node.get_kind() == clang::EntityKind::BinaryOperator
+                || node.get_kind() == clang::EntityKind::CompoundAssignOperator
+            {
                 println!(
                     "binop: lhs = {}, rhs = {}",
                     get_entity_spelling(&node.get_child(0).unwrap())
@@ -223,87 +848,1113 @@ impl<'a> WalkContext<'a> {
                     return self.analyze_entity(n, p);
                 });
 
+                // An operand without an object leaves the result without one, too.
                 if lhs_object.is_none() {
+                    self.object_name = None;
                     return clang::EntityVisitResult::Continue;
                 }
                 let lhs_object = lhs_object.unwrap();
 
                 let operator = get_binary_operator(&node);
-                if operator.is_none() {
+                if operator.is_none() || self.object_name.is_none() {
+                    self.object_name = None;
                     return clang::EntityVisitResult::Continue;
                 }
 
-                let operator = operator.unwrap();
-                if operator == "="
-                    || operator == "+"
-                    || operator == "-"
-                    || operator == "<"
-                    || operator == "<="
-                    || operator == ">"
-                    || operator == ">="
-                {
-                    let lobj = Rc::new(constraints::Object::new(&lhs_object));
-                    let repair_term = self.fresh_variable();
-                    let repair_constant = Rc::new(constraints::Object::new(&repair_term));
-                    let robj = Rc::new(constraints::Object::new(
-                        &self.object_name.as_ref().unwrap(),
-                    ));
+                let rhs_object = self.object_name.clone().unwrap();
+                if let Some(pointer) = self.get_rebound_pointer(&node, operator.as_deref()) {
+                    // Assigning to a pointer points it at a new target.
+                    self.aliases.insert(pointer, rhs_object.clone());
+                    self.object_name = Some(rhs_object);
+                    return clang::EntityVisitResult::Continue;
+                }
+
+                self.object_name = self.apply_binary_operator(
+                    &operator.unwrap(),
+                    &lhs_object,
+                    &rhs_object,
+                    &node,
+                    &node.get_child(1).unwrap(),
+                );
+                return clang::EntityVisitResult::Continue;
+            }
+
+            return clang::EntityVisitResult::Recurse;
+        }
+    }
+
+    // Whether a function, class or global variable definition was walked before. Namespaces are
+    // reopened, and instantiations are tracked separately.
+    fn is_walked_again(&mut self, node: &clang::Entity) -> bool {
+        let is_global_variable = node.get_kind() == clang::EntityKind::VarDecl
+            && node.get_semantic_parent().is_some_and(|parent| {
+                [
+                    clang::EntityKind::TranslationUnit,
+                    clang::EntityKind::Namespace,
+                ]
+                .contains(&parent.get_kind())
+            });
+        let is_record = [
+            clang::EntityKind::ClassDecl,
+            clang::EntityKind::StructDecl,
+            clang::EntityKind::UnionDecl,
+        ]
+        .contains(&node.get_kind());
+        if self.instantiation.is_some()
+            || !node.is_definition()
+            || !(is_function(node) || is_record || is_global_variable)
+        {
+            return false;
+        }
+
+        match node.get_usr() {
+            Some(usr) => !self.walked_definitions.insert(usr.0),
+            None => false,
+        }
+    }
+
+    // Names a function, class or namespace in the context stack, e.g. `cm()` or `C`.
+    fn get_context_name(&self, node: &clang::Entity) -> Option<String> {
+        get_readable_name(node)
+    }
+
+    fn spell_location(&self, entity: &clang::Entity) -> String {
+        let location = match &self.instantiation {
+            Some(_) => format!(
+                "{} in {}",
+                spell_source_location(entity),
+                self.context.join("::")
+            ),
+            None => spell_source_location(entity),
+        };
+        match self.get_macro_expansion(entity) {
+            Some((expansion, _)) => format!(
+                "{} in expansion of {} defined at {}",
+                location, expansion.name, expansion.definition_location
+            ),
+            None => location,
+        }
+    }
+
+    // Whether an entity is in the main file or in a header the file filter selects.
+    fn is_analyzed(&self, entity: &clang::Entity) -> bool {
+        if entity.is_in_main_file() {
+            return true;
+        }
+        let file = entity
+            .get_location()
+            .and_then(|location| location.get_file_location().file);
+        match file {
+            Some(file) => self
+                .file_filter
+                .includes_header(&file.get_path(), entity.is_in_system_header()),
+            None => true,
+        }
+    }
+
+    // Looks up the unit of a declaration's type, substituting template arguments.
+    fn get_declared_type_unit(&self, node: &clang::Entity) -> Option<types::Type> {
+        if ![
+            clang::EntityKind::VarDecl,
+            clang::EntityKind::FieldDecl,
+            clang::EntityKind::ParmDecl,
+        ]
+        .contains(&node.get_kind())
+        {
+            return None;
+        }
+
+        let spelling = node.get_type()?.get_display_name();
+        let spelling = spelling.trim_start_matches("const ").to_string();
+        let spelling = self
+            .template_substitutions
+            .get(&spelling)
+            .cloned()
+            .unwrap_or(spelling);
+        self.type_units.get(&spelling).copied()
+    }
+
+    // Analyzes the template instantiation a declaration's type or a call refers to, if any.
+    fn discover_instantiation(&mut self, node: &clang::Entity) {
+        let specialization = if node.is_declaration() {
+            node.get_type().and_then(get_class_specialization)
+        } else if node.get_kind() == clang::EntityKind::CallExpr {
+            node.get_reference()
+                .and_then(|callee| get_function_specialization(&callee))
+        } else {
+            None
+        };
+        if let Some((template, arguments)) = specialization {
+            self.walk_instantiation(template, arguments);
+        }
+    }
+
+    // Spells template arguments with what they are bound to in the instantiation being walked.
+    fn substitute_arguments(&self, arguments: Vec<String>) -> Vec<String> {
+        arguments
+            .into_iter()
+            .map(|argument| {
+                self.template_substitutions
+                    .get(&argument)
+                    .cloned()
+                    .unwrap_or(argument)
+            })
+            .collect()
+    }
+
+    // Names an instantiation after its template and arguments, e.g. `Filter<Meters>`.
+    fn instantiation_name(&self, template: &clang::Entity, arguments: Vec<String>) -> String {
+        format!(
+            "{}<{}>",
+            get_qualified_name(template),
+            self.substitute_arguments(arguments).join(", ")
+        )
+    }
+
+    // Walks a class or function template with its parameters bound to the given arguments.
+    // Member functions are only walked once they are used, see use_instantiated_function.
+    fn walk_instantiation(&mut self, template: clang::Entity, arguments: Vec<String>) {
+        if template.is_in_system_header() {
+            return;
+        }
+
+        let name = self.instantiation_name(&template, arguments.clone());
+        if self.instantiations.contains_key(&name) {
+            return;
+        }
+
+        let arguments = self.substitute_arguments(arguments);
+        let parameters = template.get_children().into_iter().filter(|child| {
+            [
+                clang::EntityKind::TemplateTypeParameter,
+                clang::EntityKind::NonTypeTemplateParameter,
+            ]
+            .contains(&child.get_kind())
+        });
+        let substitutions: HashMap<String, String> = parameters
+            .zip(arguments)
+            .filter_map(|(parameter, argument)| parameter.get_name().map(|p| (p, argument)))
+            .collect();
+        self.instantiations.insert(name.clone(), substitutions);
+
+        self.in_instantiation(&template, &name, |walker| {
+            // The returns of a function template give the result of its instantiation.
+            let saved_function = walker.current_function.take();
+            if template.get_kind() == clang::EntityKind::FunctionTemplate {
+                if let Some(callable) = walker.name_declaration(&template) {
+                    walker.register_callable(&callable, &template);
+                    walker.current_function = Some(callable);
+                }
+            }
+
+            template.visit_children(|n, p| walker.analyze_entity(n, p));
+
+            // Members defined outside of the class body are separate entities.
+            for member in template.get_children() {
+                if let Some(definition) = member.get_definition() {
+                    if definition != member && definition.get_lexical_parent() != Some(template) {
+                        walker.analyze_entity(definition, template);
+                    }
+                }
+            }
+            walker.current_function = saved_function;
+        });
+    }
+
+    // Runs f as if walking an instantiation, so that the template's declarations are named as the
+    // instantiation's.
+    fn in_instantiation<T>(
+        &mut self,
+        template: &clang::Entity,
+        name: &str,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        let substitutions = self.instantiations.get(name).cloned().unwrap_or_default();
+        let saved_context = std::mem::replace(&mut self.context, vec![String::from(name)]);
+        let saved_instantiation = self.instantiation.replace(String::from(name));
+        let saved_template = std::mem::replace(
+            &mut self.instantiation_template,
+            template.get_usr().map(|usr| usr.0),
+        );
+        let saved_substitutions =
+            std::mem::replace(&mut self.template_substitutions, substitutions);
+        let saved_object_name = self.object_name.take();
+
+        let result = f(self);
+
+        self.context = saved_context;
+        self.instantiation = saved_instantiation;
+        self.instantiation_template = saved_template;
+        self.template_substitutions = saved_substitutions;
+        self.object_name = saved_object_name;
+        result
+    }
+
+    // Finds the template, instantiation and template member a reference to a member of a class
+    // template specialization, such as `meters.estimate`, is about. The instantiation is named
+    // after the type of the member's base, which keeps typedefs passed as arguments apart.
+    fn get_instantiated_member<'b>(
+        &self,
+        base: Option<clang::Entity<'b>>,
+        member: &clang::Entity<'b>,
+    ) -> Option<(clang::Entity<'b>, String, clang::Entity<'b>)> {
+        let base_type = base?.get_type()?;
+        let base_type = base_type.get_pointee_type().unwrap_or(base_type);
+        let (template, arguments) = get_class_specialization(base_type)?;
+        let pattern = member
+            .get_template()
+            .filter(|pattern| {
+                pattern
+                    .get_semantic_parent()
+                    .and_then(|parent| parent.get_usr())
+                    == template.get_usr()
+            })
+            .or_else(|| {
+                template.get_children().into_iter().find(|child| {
+                    child.get_kind() == member.get_kind() && child.get_name() == member.get_name()
+                })
+            })?;
+        Some((
+            template,
+            self.instantiation_name(&template, arguments),
+            pattern,
+        ))
+    }
+
+    // Finds the template, instantiation and template function a direct call is to, if the callee
+    // is a function template specialization or a member function of an instantiation.
+    fn get_instantiated_callee<'b>(
+        &self,
+        call: &clang::Entity<'b>,
+        function: &clang::Entity<'b>,
+    ) -> Option<(clang::Entity<'b>, String, clang::Entity<'b>)> {
+        if let Some((template, arguments)) = get_function_specialization(function) {
+            return Some((
+                template,
+                self.instantiation_name(&template, arguments),
+                template,
+            ));
+        }
+        if let Some(instantiated) =
+            self.get_instantiated_member(get_member_call_object(call), function)
+        {
+            return Some(instantiated);
+        }
+
+        // Inside an instantiation, calls to the template's own members refer to its templated
+        // declarations.
+        let parent = function.get_semantic_parent()?;
+        if self.instantiation_template.is_none()
+            || parent.get_usr().map(|usr| usr.0) != self.instantiation_template
+        {
+            return None;
+        }
+        Some((parent, self.instantiation.clone()?, *function))
+    }
+
+    // Names a function of an instantiation and registers its parameters. Member functions are
+    // walked in the instantiation the first time they are used.
+    fn use_instantiated_function(
+        &mut self,
+        template: &clang::Entity,
+        name: &str,
+        function: &clang::Entity,
+    ) -> Option<String> {
+        let callable = self.in_instantiation(template, name, |walker| {
+            let callable = walker.name_declaration(function)?;
+            walker.register_callable(&callable, function);
+            Some(callable)
+        })?;
+        if function.get_kind() == clang::EntityKind::Method
+            && self.walked_members.insert(callable.clone())
+        {
+            if let Some(definition) = function.get_definition() {
+                let saved_member = self.walking_member.replace(callable.clone());
+                self.in_instantiation(template, name, |walker| {
+                    walker.analyze_entity(definition, *template)
+                });
+                self.walking_member = saved_member;
+            }
+        }
+        Some(callable)
+    }
+
+    // Whether a node is the definition of a member function of the instantiation being walked
+    // that is not being walked for a use of it.
+    fn is_unused_member(&mut self, node: &clang::Entity) -> bool {
+        if self.instantiation.is_none()
+            || node.get_kind() != clang::EntityKind::Method
+            || !node.is_definition()
+            || node
+                .get_semantic_parent()
+                .and_then(|parent| parent.get_usr())
+                .map(|usr| usr.0)
+                != self.instantiation_template
+        {
+            return false;
+        }
+        self.name_declaration(node) != self.walking_member
+    }
+
+    // Records the parameter objects of a function or lambda.
+    fn register_callable(&mut self, callable: &str, function: &clang::Entity) {
+        // Function templates have no arguments of their own, only their templated declaration.
+        let parameters = function
+            .get_arguments()
+            .unwrap_or_else(|| {
+                function
+                    .get_children()
+                    .into_iter()
+                    .filter(|child| child.get_kind() == clang::EntityKind::ParmDecl)
+                    .collect()
+            })
+            .iter()
+            .enumerate()
+            .map(|(index, parameter)| {
+                self.name_declaration(parameter)
+                    .unwrap_or(format!("{}::{}", callable, index))
+            })
+            .collect();
+        self.callable_parameters
+            .insert(String::from(callable), parameters);
+    }
+
+    // Binds arguments to the parameters of a callable; the result has the callable's return unit.
+    fn apply_invocation(
+        &mut self,
+        call: &clang::Entity,
+        callable: &str,
+        arguments: &[clang::Entity],
+    ) {
+        let parameters = self
+            .callable_parameters
+            .get(callable)
+            .cloned()
+            .unwrap_or_default();
+        for (argument, parameter) in arguments.iter().zip(parameters.iter()) {
+            if let Some(argument_object) = self.analyze_expression(argument, call) {
+                self.push_repairable(parameter, &argument_object, argument);
+            }
+        }
+        self.object_name = Some(self.return_object(callable));
+    }
+
+    // Names the object holding a callable's result.
+    fn return_object(&mut self, callable: &str) -> String {
+        let id = format!("{}::return", callable);
+        let label = format!("{}::return", self.display_name(callable));
+        self.display_names.entry(id.clone()).or_insert(label);
+        id
+    }
+
+    // Checks that an overriding method keeps the parameter and result units of the methods it
+    // overrides.
+    fn check_overrides(&mut self, method: &clang::Entity, callable: &str) {
+        let parameters = self
+            .callable_parameters
+            .get(callable)
+            .cloned()
+            .unwrap_or_default();
+        let arguments = method.get_arguments().unwrap_or_default();
+        let method_name = get_readable_qualified_name(method);
+
+        for base in method.get_overridden_methods().unwrap_or_default() {
+            let Some(base_callable) = self.name_declaration(&base) else {
+                continue;
+            };
+            self.register_callable(&base_callable, &base);
+            let base_parameters = self.callable_parameters[&base_callable].clone();
+            let base_name = get_readable_qualified_name(&base);
+
+            for (index, (parameter, base_parameter)) in
+                parameters.iter().zip(base_parameters.iter()).enumerate()
+            {
+                let requirement = UnitRequirement::SameScaledUnits(
+                    self.object(parameter),
+                    self.object(base_parameter),
+                );
+                self.push_unit_check(
+                    requirement,
+                    format!("unit of parameter {} differs from {}", index + 1, base_name),
+                    &arguments[index],
+                );
+            }
+
+            let return_object = self.return_object(callable);
+            let base_return_object = self.return_object(&base_callable);
+            self.unit_checks.push(UnitCheck {
+                requirement: UnitRequirement::SameScaledUnits(
+                    self.object(&return_object),
+                    self.object(&base_return_object),
+                ),
+                message: format!("result unit differs from {}", base_name),
+                context: RepairContext {
+                    original_expression: method_name.clone(),
+                    ..self.repair_context(method, method)
+                },
+            });
+        }
+    }
+
+    // Looks up the dependent exponent signature of a function. The annotated declaration may come
+    // after the call, so the comments of the function's first declaration and definition are read
+    // too.
+    fn get_annotated_signature(
+        &mut self,
+        function: &clang::Entity,
+    ) -> Option<signatures::Signature> {
+        let name = get_qualified_name(function);
+        if let Some(signature) = self.function_signatures.get(&name) {
+            return Some(*signature);
+        }
+
+        let signature = [
+            Some(function.get_canonical_entity()),
+            function.get_definition(),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|declaration| declaration.get_parsed_comment())
+        .find_map(|comment| {
+            signatures::parse_signature_comment(&get_comment_text(&comment))
+                .map(|(_, signature)| signature)
+        })?;
+        self.function_signatures.insert(name, signature);
+        Some(signature)
+    }
+
+    // Walks a lambda's parameters and body and returns its callable name.
+    fn walk_lambda(&mut self, lambda: &clang::Entity) -> String {
+        let (_, line, column) = lambda
+            .get_location()
+            .map(|location| location.get_presumed_location())
+            .unwrap_or((String::new(), 0, 0));
+        let name = format!("lambda@{}:{}", line, column);
+        let callable = format!("lambda:{}", spell_source_location(lambda));
+        self.display_names
+            .insert(callable.clone(), self.qualify_name(&name));
+        self.context.push(name);
+
+        let parameters: Vec<String> = lambda
+            .get_children()
+            .iter()
+            .filter(|child| child.get_kind() == clang::EntityKind::ParmDecl)
+            .enumerate()
+            .map(|(index, parameter)| {
+                self.name_declaration(parameter)
+                    .unwrap_or(format!("{}::{}", callable, index))
+            })
+            .collect();
+        self.callable_parameters
+            .insert(callable.clone(), parameters);
+        let saved_function = self.current_function.replace(callable.clone());
+
+        lambda.visit_children(|n, p| self.analyze_entity(n, p));
+
+        self.current_function = saved_function;
+        self.context.pop();
+        callable
+    }
+
+    // Names the member a member expression such as `this->member` or `meters.estimate`, or a member
+    // initializer, refers to. Members of instantiations are named as they are in the instantiation.
+    fn get_member_object(&mut self, member: &clang::Entity) -> Option<String> {
+        let field = member.get_reference()?;
+        match self.get_instantiated_member(member.get_child(0), &field) {
+            Some((template, name, pattern)) => {
+                self.in_instantiation(&template, &name, |walker| walker.name_declaration(&pattern))
+            }
+            None => self.name_declaration(&field),
+        }
+    }
+
+    // Walks a constructor, treating each member initializer as an initialization of the member.
+    fn walk_constructor(&mut self, constructor: &clang::Entity) {
+        let children = constructor.get_children();
+        let mut index = 0;
+        while index < children.len() {
+            let child = &children[index];
+            let initializer = children.get(index + 1).filter(|next| next.is_expression());
+            if let (clang::EntityKind::MemberRef, Some(initializer)) =
+                (child.get_kind(), initializer)
+            {
+                let member_object = self.get_member_object(child);
+                let rhs_object = self.analyze_expression(initializer, constructor);
+                if let (Some(member_object), Some(rhs_object)) = (member_object, rhs_object) {
+                    self.push_initialization(&member_object, &rhs_object, initializer, initializer);
+                }
+                index += 2;
+                continue;
+            }
+
+            self.analyze_expression(child, constructor);
+            index += 1;
+        }
+        self.object_name = None;
+    }
+
+    // Asserts that an initializer can be repaired into the object it initializes.
+    fn push_initialization(
+        &mut self,
+        lhs_object: &str,
+        rhs_object: &str,
+        located_at: &clang::Entity,
+        initializer: &clang::Entity,
+    ) {
+        println!(
+            "Visiting lhs {} and rhs {} in assignment.",
+            lhs_object, rhs_object
+        );
+
+        let mut count = self.fresh_count;
+        let naming_fn = || {
+            let name = format!("T{}", count);
+            count += 1;
+            return name;
+        };
+
+        let frame_repair_name = frames::on_frame_assignment(
+            lhs_object,
+            rhs_object,
+            self.z3_solver,
+            &mut self.object_name_to_frame_var,
+            &mut self.frame_conversion_name_to_conversion,
+            &mut self.frame_repair_consts,
+            naming_fn,
+        );
+        self.fresh_count = count;
+        self.frame_conversion_name_to_repair_context.insert(
+            frame_repair_name,
+            self.repair_context(located_at, initializer),
+        );
+
+        let lobj = Rc::new(self.object(lhs_object));
+        let repair_term = self.fresh_variable();
+        let repair_constant = Rc::new(self.object(&repair_term));
+        let robj = Rc::new(self.object(rhs_object));
+
+        self.tmp_terms_to_repair_contexts.insert(
+            self.object(&repair_term),
+            self.repair_context(located_at, initializer),
+        );
+
+        let constraint = constraints::assert_repairable(lobj, robj, repair_constant);
+        self.constraints.push(constraint);
+    }
+
+    // Follows references and pointers to the object they are bound to.
+    fn resolve_alias(&self, name: String) -> String {
+        let mut resolved = name;
+        for _ in 0..self.aliases.len() {
+            match self.aliases.get(&resolved) {
+                Some(target) => resolved = target.clone(),
+                None => break,
+            }
+        }
+        resolved
+    }
+
+    // Returns the pointer variable an assignment such as `p = &y` rebinds, if any.
+    fn get_rebound_pointer(
+        &mut self,
+        assignment: &clang::Entity,
+        operator: Option<&str>,
+    ) -> Option<String> {
+        if operator != Some("=") {
+            return None;
+        }
+
+        let mut lhs = assignment.get_child(0)?;
+        while lhs.is_unexposed() {
+            lhs = lhs.get_child(0)?;
+        }
+        let is_pointer = lhs
+            .get_type()
+            .is_some_and(|t| t.get_canonical_type().get_kind() == clang::TypeKind::Pointer);
+        if lhs.get_kind() != clang::EntityKind::DeclRefExpr || !is_pointer {
+            return None;
+        }
+
+        let pointer = lhs.get_reference()?;
+        self.name_declaration(&pointer)
+    }
 
-                    let original_expression = node
-                        .get_child(1)
-                        .and_then(|entity| get_entity_spelling(&entity))
-                        .unwrap_or(String::from("Unknown spelling"));
-                    let source_location = spell_source_location(&node.get_child(1).unwrap());
-                    self.tmp_terms_to_repair_contexts.insert(
-                        constraints::Object::new(&repair_term),
-                        RepairContext {
-                            source_location,
-                            original_expression,
-                        },
+    // Adds the constraints of a binary operator and returns the object holding the result's type.
+    fn apply_binary_operator(
+        &mut self,
+        operator: &str,
+        lhs_object: &str,
+        rhs_object: &str,
+        expression: &clang::Entity,
+        rhs: &clang::Entity,
+    ) -> Option<String> {
+        match operator {
+            "=" | "+" | "-" | "+=" | "-=" | "%" | "%=" => {
+                // The remainder keeps the dividend's unit, so the divisor must match it.
+                self.push_repairable(lhs_object, rhs_object, rhs);
+                Some(String::from(lhs_object))
+            }
+            "<" | "<=" | ">" | ">=" | "==" | "!=" => {
+                // The operands are compared in one unit, but the result is a truth value.
+                self.push_repairable(lhs_object, rhs_object, rhs);
+                Some(self.fresh_dimensionless_variable())
+            }
+            "<<" | ">>" => {
+                // Shifting by k scales by 2^k, which matters for fixed-point code.
+                let scale_term = self.fresh_variable();
+                let scale = Rc::new(self.object(&scale_term));
+                self.constraints
+                    .push(constraints::assert_dimensionless(scale.clone()));
+                if let Some(shift) = evaluate_number(rhs) {
+                    let factor = if operator == "<<" {
+                        2f64.powf(shift)
+                    } else {
+                        2f64.powf(-shift)
+                    };
+                    self.constraints
+                        .push(constraints::assert_literal(factor, scale.clone()));
+                }
+
+                let type_term = self.fresh_variable();
+                let constraint = constraints::create_multiplicative_type(
+                    Rc::new(self.object(&type_term)),
+                    Rc::new(self.object(lhs_object)),
+                    scale,
+                );
+                self.constraints.push(constraint);
+                Some(type_term)
+            }
+            "&" | "|" | "^" | "&&" | "||" | "&=" | "|=" | "^=" => {
+                for operand in [lhs_object, rhs_object] {
+                    self.push_unit_check(
+                        UnitRequirement::Dimensionless(self.object(operand)),
+                        format!("operands of {} must be dimensionless", operator),
+                        expression,
                     );
+                }
+                Some(self.fresh_dimensionless_variable())
+            }
+            "*=" | "/=" | "<<=" | ">>=" => {
+                // The product, quotient or shifted value is assigned back, so scaling the rhs
+                // repairs it.
+                let type_term = self.apply_binary_operator(
+                    &operator[..operator.len() - 1],
+                    lhs_object,
+                    rhs_object,
+                    expression,
+                    rhs,
+                )?;
+                self.push_repairable(lhs_object, &type_term, rhs);
+                Some(String::from(lhs_object))
+            }
+            "*" => {
+                let type_term = self.fresh_variable();
+                let constraint = constraints::create_multiplicative_type(
+                    Rc::new(self.object(&type_term)),
+                    Rc::new(self.object(lhs_object)),
+                    Rc::new(self.object(rhs_object)),
+                );
+                self.constraints.push(constraint);
+                Some(type_term)
+            }
+            "/" => {
+                let type_term = self.fresh_variable();
+                let constraint = constraints::create_division_type(
+                    Rc::new(self.object(&type_term)),
+                    Rc::new(self.object(lhs_object)),
+                    Rc::new(self.object(rhs_object)),
+                );
+                self.constraints.push(constraint);
+                Some(type_term)
+            }
+            _ => None,
+        }
+    }
 
-                    let constraint = constraints::assert_repairable(lobj, robj, repair_constant);
-                    self.constraints.push(constraint);
+    // Types a call to an overloaded operator, e.g. `a + b` on Eigen vectors.
+    fn apply_overloaded_operator(&mut self, call: &clang::Entity, operator: &str) {
+        let arguments = call.get_arguments().unwrap_or_default();
+        if operator == "[]" && arguments.len() == 2 {
+            self.object_name = self.apply_subscript(call, &arguments[0], &arguments[1]);
+            return;
+        }
 
-                    self.object_name = Some(lhs_object);
-                    return clang::EntityVisitResult::Continue;
-                } else if operator == "*" {
-                    let lobj = Rc::new(constraints::Object::new(&lhs_object));
-                    let type_term = self.fresh_variable();
-                    let type_constant = Rc::new(constraints::Object::new(&type_term));
-                    let robj = Rc::new(constraints::Object::new(
-                        &self.object_name.as_ref().unwrap(),
+        // Invoking a lambda or std::function binds the arguments to the callable's parameters.
+        if operator == "()" && !arguments.is_empty() {
+            let callable = self
+                .analyze_expression(&arguments[0], call)
+                .filter(|callable| self.callable_parameters.contains_key(callable));
+            if let Some(callable) = callable {
+                self.apply_invocation(call, &callable, &arguments[1..]);
+                return;
+            }
+        }
+
+        let operands: Vec<Option<String>> = arguments
+            .iter()
+            .map(|argument| self.analyze_expression(argument, call))
+            .collect();
+
+        self.object_name = match operands.as_slice() {
+            // Dereferencing an iterator yields an element of its container.
+            [Some(operand)] if ["+", "-", "*", "->", "++", "--"].contains(&operator) => {
+                Some(operand.clone())
+            }
+            // Overloaded shifts such as `std::cout << x` stream values rather than scaling them.
+            [Some(_), Some(_)]
+                if ["<<", ">>"].contains(&operator) && !arguments.iter().all(is_arithmetic) =>
+            {
+                None
+            }
+            [Some(lhs_object), Some(rhs_object)] => {
+                self.apply_binary_operator(operator, lhs_object, rhs_object, call, &arguments[1])
+            }
+            _ => None,
+        };
+    }
+
+    // Elements share the unit of their container, and indices must be dimensionless.
+    fn apply_subscript(
+        &mut self,
+        expression: &clang::Entity,
+        container: &clang::Entity,
+        index: &clang::Entity,
+    ) -> Option<String> {
+        let container_object = self.analyze_expression(container, expression);
+        if let Some(index_object) = self.analyze_expression(index, expression) {
+            self.push_unit_check(
+                UnitRequirement::Dimensionless(self.object(&index_object)),
+                String::from("index must be dimensionless"),
+                index,
+            );
+        }
+        container_object
+    }
+
+    // Types a call such as `v.at(i)` or `v.push_back(x)` on a standard container.
+    fn apply_container_method(
+        &mut self,
+        call: &clang::Entity,
+        container: &clang::Entity,
+        method: signatures::ContainerMethod,
+    ) {
+        let container_object = self.analyze_expression(container, call);
+        let arguments = call.get_arguments().unwrap_or_default();
+        let argument_objects: Vec<Option<String>> = arguments
+            .iter()
+            .map(|argument| self.analyze_expression(argument, call))
+            .collect();
+
+        self.object_name = match method {
+            signatures::ContainerMethod::Element => {
+                for (argument, index_object) in arguments.iter().zip(argument_objects.iter()) {
+                    if let Some(index_object) = index_object {
+                        self.push_unit_check(
+                            UnitRequirement::Dimensionless(self.object(index_object)),
+                            String::from("index must be dimensionless"),
+                            argument,
+                        );
+                    }
+                }
+                container_object
+            }
+            signatures::ContainerMethod::Insert => {
+                if let (Some(container_object), Some(Some(element_object)), Some(argument)) =
+                    (&container_object, argument_objects.last(), arguments.last())
+                {
+                    self.push_repairable(container_object, element_object, argument);
+                }
+                None
+            }
+        };
+    }
+
+    // Initializes fields from an initializer list, in order or by designators such as `.lat = x`.
+    fn apply_record_initialization(
+        &mut self,
+        initializer: &clang::Entity,
+        fields: &[clang::Entity],
+    ) {
+        let mut next_field = 0;
+        for element in initializer.get_children() {
+            let children = element.get_children();
+            let designator = children
+                .iter()
+                .rev()
+                .find(|child| child.get_kind() == clang::EntityKind::MemberRef);
+            let (field, value) = match designator {
+                Some(designator) => {
+                    let field = designator.get_reference();
+                    next_field = fields
+                        .iter()
+                        .position(|f| Some(*f) == field)
+                        .map_or(fields.len(), |position| position + 1);
+                    (field, children.last().copied())
+                }
+                None => {
+                    next_field += 1;
+                    (fields.get(next_field - 1).copied(), Some(element))
+                }
+            };
+            let (Some(field), Some(value)) = (field, value) else {
+                continue;
+            };
+
+            let nested_fields = get_record_fields(&field);
+            if let (clang::EntityKind::InitListExpr, Some(nested_fields)) =
+                (value.get_kind(), nested_fields)
+            {
+                self.apply_record_initialization(&value, &nested_fields);
+                continue;
+            }
+
+            let field_object = self.name_declaration(&field);
+            let value_object = self.analyze_expression(&value, initializer);
+            if let (Some(field_object), Some(value_object)) = (field_object, value_object) {
+                self.push_initialization(&field_object, &value_object, &value, &value);
+            }
+        }
+    }
+
+    // Gives the variable of a range-for loop the unit of the range's elements.
+    fn apply_range_for(&mut self, statement: &clang::Entity) {
+        let children = statement.get_children();
+        let variable = children
+            .iter()
+            .find(|child| child.get_kind() == clang::EntityKind::VarDecl);
+        let range = children.iter().find(|child| child.is_expression());
+        if let (Some(variable), Some(range)) = (variable, range) {
+            let range_object = self.analyze_expression(range, statement);
+            let variable_object = self.name_declaration(variable);
+            if let (Some(range_object), Some(variable_object)) = (range_object, variable_object) {
+                if is_alias_type(variable) {
+                    self.aliases.insert(variable_object, range_object);
+                } else {
+                    self.push_repairable(&variable_object, &range_object, range);
+                }
+            }
+        }
+
+        for child in children
+            .iter()
+            .filter(|child| Some(*child) != variable && Some(*child) != range)
+        {
+            self.analyze_expression(child, statement);
+        }
+        self.object_name = None;
+    }
+
+    // Analyzes a subexpression and returns the name of the object holding its type.
+    fn analyze_expression(
+        &mut self,
+        expression: &clang::Entity,
+        parent: &clang::Entity,
+    ) -> Option<String> {
+        self.object_name = None;
+        if self.analyze_entity(*expression, *parent) == clang::EntityVisitResult::Recurse {
+            expression.visit_children(|n, p| self.analyze_entity(n, p));
+        }
+        self.object_name.clone()
+    }
+
+    // Asserts that the rhs object can be repaired into the lhs object.
+    fn push_repairable(&mut self, lhs_object: &str, rhs_object: &str, rhs: &clang::Entity) {
+        let repair_term = self.fresh_variable();
+        self.tmp_terms_to_repair_contexts
+            .insert(self.object(&repair_term), self.repair_context(rhs, rhs));
+
+        let constraint = constraints::assert_repairable(
+            Rc::new(self.object(lhs_object)),
+            Rc::new(self.object(rhs_object)),
+            Rc::new(self.object(&repair_term)),
+        );
+        self.constraints.push(constraint);
+    }
+
+    fn push_unit_check(
+        &mut self,
+        requirement: UnitRequirement,
+        message: String,
+        expression: &clang::Entity,
+    ) {
+        self.unit_checks.push(UnitCheck {
+            requirement,
+            message,
+            context: self.repair_context(expression, expression),
+        });
+    }
+
+    fn repair_context(
+        &self,
+        located_at: &clang::Entity,
+        expression: &clang::Entity,
+    ) -> RepairContext {
+        // Other invocations share a macro's body, so its expressions are repaired at the invocation.
+        let original_expression = match self.get_macro_body_expansion(expression) {
+            Some(expansion) => expansion.invocation.clone(),
+            None => get_entity_spelling(expression).unwrap_or(String::from("Unknown spelling")),
+        };
+        RepairContext {
+            source_location: self.spell_location(located_at),
+            file: get_file_name(located_at),
+            original_expression,
+        }
+    }
+
+    // Types a call to a function with a known unit signature.
+    fn apply_signature(&mut self, call: &clang::Entity, signature: signatures::Signature) {
+        let callee_name = call.get_name().unwrap_or(String::from("Unknown function"));
+        let arguments = call.get_arguments().unwrap_or_default();
+        let argument_objects: Vec<Option<String>> = arguments
+            .iter()
+            .map(|argument| self.analyze_expression(argument, call))
+            .collect();
+
+        let result_term = self.fresh_variable();
+        let result = Rc::new(self.object(&result_term));
+        match signature {
+            signatures::Signature::Uniform => {
+                let mut typed_arguments = arguments
+                    .iter()
+                    .zip(argument_objects.iter())
+                    .filter_map(|(argument, object)| object.as_ref().map(|o| (argument, o)));
+                if let Some((_, first_object)) = typed_arguments.next() {
+                    self.constraints.push(constraints::assert_equal(
+                        result.clone(),
+                        Rc::new(self.object(first_object)),
                     ));
-                    let constraint =
-                        constraints::create_multiplicative_type(type_constant, lobj, robj);
-                    self.constraints.push(constraint);
-                    self.object_name = Some(type_term);
-                    return clang::EntityVisitResult::Continue;
-                } else if operator == "/" {
-                    let lobj = Rc::new(constraints::Object::new(&lhs_object));
-                    let type_term = self.fresh_variable();
-                    let type_constant = Rc::new(constraints::Object::new(&type_term));
-                    let robj = Rc::new(constraints::Object::new(
-                        &self.object_name.as_ref().unwrap(),
+                    for (argument, object) in typed_arguments {
+                        self.push_repairable(&result_term, object, argument);
+                        self.push_unit_check(
+                            UnitRequirement::SameUnits(
+                                self.object(first_object),
+                                self.object(object),
+                            ),
+                            format!("arguments of {} have different units", callee_name),
+                            call,
+                        );
+                    }
+                }
+            }
+            signatures::Signature::Exponent(exponent) => {
+                if let Some(Some(base)) = argument_objects.first() {
+                    self.constraints.push(constraints::create_power_type(
+                        result.clone(),
+                        Rc::new(self.object(base)),
+                        exponent,
                     ));
-                    let constraint = constraints::create_division_type(type_constant, lobj, robj);
-                    self.constraints.push(constraint);
-                    self.object_name = Some(type_term);
-                    return clang::EntityVisitResult::Continue;
                 }
             }
+            signatures::Signature::Power { base, exponent } => {
+                let exponent_value = arguments.get(exponent).and_then(evaluate_number);
+                match (argument_objects.get(base), exponent_value) {
+                    (Some(Some(base)), Some(exponent_value)) => {
+                        self.constraints.push(constraints::create_power_type(
+                            result.clone(),
+                            Rc::new(self.object(base)),
+                            exponent_value,
+                        ));
+                    }
+                    (Some(Some(base)), None) => {
+                        // Only a dimensionless base has a known unit for any exponent.
+                        self.push_unit_check(
+                            UnitRequirement::Dimensionless(self.object(base)),
+                            format!(
+                                "base of {} must be dimensionless when the exponent is not constant",
+                                callee_name
+                            ),
+                            call,
+                        );
+                        self.constraints
+                            .push(constraints::assert_dimensionless(result.clone()));
+                    }
+                    _ => {}
+                }
+            }
+            signatures::Signature::Dimensionless => {
+                for object in argument_objects.iter().flatten() {
+                    self.push_unit_check(
+                        UnitRequirement::Dimensionless(self.object(object)),
+                        format!("argument of {} must be dimensionless", callee_name),
+                        call,
+                    );
+                }
+                self.constraints
+                    .push(constraints::assert_dimensionless(result.clone()));
+            }
+        }
 
-            return clang::EntityVisitResult::Recurse;
+        self.object_name = Some(result_term);
+    }
+
+    // Types a call to a library function declared in the signature database.
+    fn apply_library_signature(
+        &mut self,
+        call: &clang::Entity,
+        signature: &signatures::LibrarySignature,
+    ) {
+        let arguments = call.get_arguments().unwrap_or_default();
+        for (index, argument) in arguments.iter().enumerate() {
+            let argument_object = self.analyze_expression(argument, call);
+            if let (Some(Some(parameter_type)), Some(argument_object)) =
+                (signature.parameters.get(index), argument_object)
+            {
+                let parameter_term = self.fresh_variable();
+                self.constraints.push(constraints::type_to_constraint(
+                    parameter_type,
+                    Rc::new(self.object(&parameter_term)),
+                ));
+                self.push_repairable(&parameter_term, &argument_object, argument);
+            }
+        }
+
+        let result_term = self.fresh_variable();
+        if let Some(result_type) = &signature.result {
+            self.constraints.push(constraints::type_to_constraint(
+                result_type,
+                Rc::new(self.object(&result_term)),
+            ));
         }
+        if let Some((iframe, tframe)) = &signature.frame {
+            let z3_var = frames::frame_assert(&result_term, (iframe, tframe), self.z3_solver);
+            self.object_name_to_frame_var
+                .insert(result_term.clone(), z3_var);
+        }
+
+        self.object_name = Some(result_term);
     }
 
-    fn new(solver: &'a z3::Optimize<'a>) -> WalkContext<'a> {
+    pub fn new(
+        solver: &'a z3::Optimize<'a>,
+        signature_database: &'a signatures::SignatureDatabase,
+        file_filter: &'a commands::FileFilter,
+    ) -> WalkContext<'a> {
         WalkContext {
             context: vec![],
             constraints: vec![],
             object_name: None,
             fresh_count: 0,
             tmp_terms_to_repair_contexts: HashMap::new(),
+            unit_checks: Vec::new(),
+            annotations: HashMap::new(),
+            function_signatures: HashMap::new(),
+            aliases: HashMap::new(),
+            callable_parameters: HashMap::new(),
+            current_function: None,
+            type_units: HashMap::new(),
+            instantiation: None,
+            template_substitutions: HashMap::new(),
+            instantiations: HashMap::new(),
+            walked_members: HashSet::new(),
+            walking_member: None,
+            walked_definitions: HashSet::new(),
+            instantiation_template: None,
+            macro_expansions: HashMap::new(),
+            expanding_macros: HashSet::new(),
+            display_names: HashMap::new(),
+            signature_database,
+            file_filter,
+            commented_files: HashMap::new(),
             z3_solver: solver,
             object_name_to_frame_var: HashMap::new(),
             frame_conversion_name_to_conversion: HashMap::new(),
@@ -311,4 +1962,59 @@ impl<'a> WalkContext<'a> {
             frame_repair_consts: Vec::new(),
         }
     }
+
+    // Saves what solving needs. Must be called before solving adds its objective to the solver.
+    pub fn save(&self) -> SavedWalk {
+        SavedWalk {
+            constraints: self.constraints.clone(),
+            tmp_terms_to_repair_contexts: self
+                .tmp_terms_to_repair_contexts
+                .iter()
+                .map(|(term, context)| (term.clone(), context.clone()))
+                .collect(),
+            unit_checks: self.unit_checks.clone(),
+            annotations: self.annotations.clone(),
+            display_names: self.display_names.clone(),
+            frame_assertions: self.z3_solver.to_string(),
+            frame_conversion_name_to_repair_context: self
+                .frame_conversion_name_to_repair_context
+                .clone(),
+        }
+    }
+
+    // Loads a saved walk into a new context, asserting its frame constraints in the solver.
+    pub fn load(
+        saved: SavedWalk,
+        solver: &'a z3::Optimize<'a>,
+        signature_database: &'a signatures::SignatureDatabase,
+        file_filter: &'a commands::FileFilter,
+    ) -> WalkContext<'a> {
+        solver.from_string(saved.frame_assertions);
+        let mut w = WalkContext::new(solver, signature_database, file_filter);
+        w.constraints = saved.constraints;
+        w.tmp_terms_to_repair_contexts = saved.tmp_terms_to_repair_contexts.into_iter().collect();
+        w.unit_checks = saved.unit_checks;
+        w.annotations = saved.annotations;
+        w.display_names = saved.display_names;
+
+        // Constants with the same names are the same constants as in the loaded assertions.
+        let mut conversion_names: Vec<&String> = saved
+            .frame_conversion_name_to_repair_context
+            .keys()
+            .collect();
+        conversion_names.sort();
+        for name in conversion_names {
+            let context = solver.get_context();
+            w.frame_conversion_name_to_conversion.insert(
+                name.clone(),
+                Rc::new(z3::ast::Int::new_const(context, name.as_str())),
+            );
+            w.frame_repair_consts.push(Rc::new(z3::ast::Int::new_const(
+                context,
+                frames::get_repair_const_name(name),
+            )));
+        }
+        w.frame_conversion_name_to_repair_context = saved.frame_conversion_name_to_repair_context;
+        w
+    }
 }